@@ -0,0 +1,181 @@
+use crate::plan::{Plan, PlanBranch, StateId};
+use crate::{Action, State};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Decides in which order the actions of a pending state are tried.
+///
+/// The actions are given in their natural order, [`Action::Throw`] first and then the maps in the
+/// order of the divisors, and may only be permuted. Trying the promising actions first finds a
+/// good complete plan early, which makes the pruning by cost effective much sooner.
+pub trait ActionOrder {
+    fn order(&self, plan: &Plan, state: StateId, actions: &mut [Action]);
+
+    /// Tells the orders apart, so that a search is only resumed with the order it started with
+    fn name(&self) -> String;
+}
+
+/// Keep the natural order
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NaturalOrder;
+
+/// Try the maps with the most units first, then throwing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LargestMapFirst;
+
+/// Try first the actions that give the plan with the lowest cost, as estimated by a heuristic. The
+/// heuristic is not part of the name of the order
+#[derive(Debug, Clone, Copy)]
+pub struct GreedyOrder<H> {
+    heuristic: H,
+}
+
+/// Try first the action that previous plans chose for the same state
+#[derive(Debug, Clone, Default)]
+pub struct LearnedOrder {
+    choices: HashMap<State, Action>,
+}
+
+impl ActionOrder for NaturalOrder {
+    fn order(&self, _plan: &Plan, _state: StateId, _actions: &mut [Action]) {}
+
+    fn name(&self) -> String {
+        String::from("natural")
+    }
+}
+
+impl ActionOrder for LargestMapFirst {
+    fn order(&self, _plan: &Plan, _state: StateId, actions: &mut [Action]) {
+        actions.reverse();
+    }
+
+    fn name(&self) -> String {
+        String::from("largest-map")
+    }
+}
+
+impl<H: Fn(State) -> f64> GreedyOrder<H> {
+    pub fn new(heuristic: H) -> Self {
+        GreedyOrder { heuristic }
+    }
+}
+
+impl<H: Fn(State) -> f64> ActionOrder for GreedyOrder<H> {
+    fn order(&self, plan: &Plan, state: StateId, actions: &mut [Action]) {
+        // The sort is stable, so the natural order breaks the ties
+        actions.sort_by_cached_key(|&action| {
+            let mut next = plan.clone();
+            next.apply(state, action).unwrap();
+            next.cost(&self.heuristic).value.to_bits()
+        });
+    }
+
+    fn name(&self) -> String {
+        String::from("greedy")
+    }
+}
+
+impl LearnedOrder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember the actions decided in the plan. Later plans override earlier ones
+    pub fn learn(&mut self, plan: &Plan) {
+        for (&id, branch) in plan.plans() {
+            let action = match *branch {
+                PlanBranch::Solved | PlanBranch::Pending { .. } | PlanBranch::Library => continue,
+                PlanBranch::Throw { .. } => Action::Throw,
+                PlanBranch::Map { units, .. } => Action::Map(units),
+            };
+            self.choices.insert(plan.state(id), action);
+        }
+    }
+}
+
+impl ActionOrder for LearnedOrder {
+    fn order(&self, plan: &Plan, state: StateId, actions: &mut [Action]) {
+        if let Some(&choice) = self.choices.get(&plan.state(state)) {
+            if let Some(i) = actions.iter().position(|&action| action == choice) {
+                actions[..=i].rotate_right(1);
+            }
+        }
+    }
+
+    /// Includes a hash of the choices, which the default hasher computes the same way in every run
+    fn name(&self) -> String {
+        let mut choices = self
+            .choices
+            .iter()
+            .map(|(&state, &action)| match action {
+                Action::Throw => (state, 0),
+                Action::Map(units) => (state, units),
+            })
+            .collect::<Vec<_>>();
+        choices.sort_unstable();
+        let mut hasher = DefaultHasher::new();
+        choices.hash(&mut hasher);
+        format!("learned:{:016x}", hasher.finish())
+    }
+}
+
+impl<T: ActionOrder + ?Sized> ActionOrder for Box<T> {
+    fn order(&self, plan: &Plan, state: StateId, actions: &mut [Action]) {
+        (**self).order(plan, state, actions)
+    }
+
+    fn name(&self) -> String {
+        (**self).name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::divider::Divider;
+
+    fn first_actions(order: &impl ActionOrder) -> Vec<Action> {
+        let start = State {
+            source: 6,
+            target: 4,
+            units: 6,
+        };
+        let plan = Plan::new(start);
+        plan.first_pending_actions(&Divider::new(), order)
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn orders() {
+        use Action::*;
+
+        assert_eq!(first_actions(&NaturalOrder), [Throw, Map(2), Map(4)]);
+        assert_eq!(first_actions(&LargestMapFirst), [Map(4), Map(2), Throw]);
+        // Mapping 4 of the 6 units to a solved state leaves the least to solve
+        assert_eq!(
+            first_actions(&GreedyOrder::new(|state: State| state.target as f64)),
+            [Map(4), Map(2), Throw]
+        );
+
+        let mut learned = LearnedOrder::new();
+        assert_eq!(first_actions(&learned), [Throw, Map(2), Map(4)]);
+        let untrained = learned.name();
+        let mut plan = Plan::new(State {
+            source: 6,
+            target: 4,
+            units: 6,
+        });
+        plan.apply(plan.start(), Map(2)).unwrap();
+        learned.learn(&plan);
+        assert_eq!(first_actions(&learned), [Map(2), Throw, Map(4)]);
+
+        // Orders trained differently have different names
+        assert_ne!(learned.name(), untrained);
+        let mut same = LearnedOrder::new();
+        same.learn(&plan);
+        assert_eq!(same.name(), learned.name());
+        assert_ne!(NaturalOrder.name(), LargestMapFirst.name());
+    }
+}
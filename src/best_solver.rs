@@ -0,0 +1,298 @@
+mod checkpoint;
+
+use crate::action_order::ActionOrder;
+use crate::divider::Divider;
+use crate::naive_solver;
+use crate::plan::{CostModel, Objective, Plan, PlanFingerprint, StateId};
+use crate::search_stats::SearchStats;
+use crate::{Action, State};
+pub use checkpoint::Checkpoint;
+use std::collections::{HashSet, VecDeque};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// The whole state of a [`best_solver`] run, which can be saved to a [`Checkpoint`] and resumed.
+/// All its plans share the same interner
+#[derive(Debug)]
+pub struct BestSearch {
+    start: State,
+    objective: Objective,
+    /// The name of the action order, which decides the order of the queue
+    order: String,
+    pending: VecDeque<(Rc<Plan>, StateId, Action)>,
+    visited_plans: HashSet<PlanFingerprint>,
+    best_plan: Rc<Plan>,
+    best_cost: f64,
+    /// The plans that cost more in expectation are pruned
+    max_expected: f64,
+    stats: SearchStats,
+}
+
+/// Search the best plan breadth-first for the objective, pruning the plans that cost more than the
+/// best complete plan found so far.
+///
+/// The search is reproducible: pending states are expanded in the order of their ids, which are
+/// given in the order the states are discovered, and no iteration order depends on a hash: the
+/// visited plans are only looked up. So two runs with the same inputs expand the same plans in the
+/// same order, log the same lines and return the same plan.
+pub fn best_solver(
+    start: State,
+    mut heuristic: impl FnMut(State) -> f64,
+    order: &impl ActionOrder,
+    objective: Objective,
+    cost_model: CostModel,
+    checkpoint: Option<&Checkpoint>,
+) -> (Plan, SearchStats) {
+    let divider = Divider::new();
+
+    let mut search = match checkpoint {
+        Some(checkpoint) if checkpoint.exists() => {
+            let search = checkpoint.load().unwrap();
+            assert_eq!(
+                search.start, start,
+                "The checkpoint was saved for a different problem"
+            );
+            assert_eq!(
+                search.objective, objective,
+                "The checkpoint was saved for a different objective"
+            );
+            assert_eq!(
+                search.order,
+                order.name(),
+                "The checkpoint was saved for a different action order"
+            );
+            assert_eq!(
+                search.best_plan.cost_model(),
+                cost_model,
+                "The checkpoint was saved for a different cost model"
+            );
+            tracing::info!(
+                "Resuming from iteration {} with cost {:?}",
+                search.stats.generated,
+                search.best_cost
+            );
+            search
+        }
+        _ => {
+            let search = BestSearch::new(
+                start,
+                &divider,
+                order,
+                objective,
+                cost_model,
+                &mut heuristic,
+            );
+            tracing::info!("Initial cost is {:?}", search.best_cost);
+            search
+        }
+    };
+
+    while search.step(&divider, order, &mut heuristic) {
+        if search.stats.generated % 100_000 == 0 {
+            tracing::info!(
+                "Iteration {}: queue size is {}, visited {} plans",
+                search.stats.generated,
+                search.pending.len(),
+                search.visited_plans.len()
+            );
+        }
+
+        if let Some(checkpoint) = checkpoint {
+            if search.stats.generated % checkpoint.interval == 0 {
+                checkpoint.save(&search).unwrap();
+            }
+        }
+    }
+
+    search.into_result()
+}
+
+impl BestSearch {
+    /// Start a search. The search for the variance first searches the fewest expected throws, as
+    /// it only goes through the plans that may have as few
+    pub fn new(
+        start: State,
+        divider: &Divider,
+        order: &impl ActionOrder,
+        objective: Objective,
+        cost_model: CostModel,
+        heuristic: &mut impl FnMut(State) -> f64,
+    ) -> Self {
+        let mut pending = VecDeque::new();
+
+        let mut base_plan = Plan::new(start);
+        base_plan.set_cost_model(cost_model);
+        let base_plan = Rc::new(base_plan);
+        for (state, action) in base_plan.possible_actions(divider, order) {
+            pending.push_back((base_plan.clone(), state, action));
+        }
+
+        let mut best_plan = (*base_plan).clone();
+        let max_expected = match objective {
+            Objective::Variance => {
+                let expected = Objective::ExpectedThrows;
+                // As a trait object, so that the types of the nested searches do not grow forever
+                let heuristic: &mut dyn FnMut(State) -> f64 = heuristic;
+                let (fewest_throws, _) =
+                    best_solver(start, heuristic, order, expected, cost_model, None);
+                // In the interner of the search
+                best_plan.graft(best_plan.start(), &fewest_throws).unwrap();
+                best_plan.exact_cost().unwrap()
+            }
+            _ => {
+                best_plan.complete_with(naive_solver);
+                f64::INFINITY
+            }
+        };
+        let best_cost = best_plan.objective_cost(objective, |_| 0.0).value;
+        let best_plan = Rc::new(best_plan);
+
+        let mut stats = SearchStats {
+            peak_queue: pending.len(),
+            ..SearchStats::default()
+        };
+        stats.improved(Duration::ZERO, best_cost);
+
+        BestSearch {
+            start,
+            objective,
+            order: order.name(),
+            pending,
+            visited_plans: HashSet::new(),
+            best_plan,
+            best_cost,
+            max_expected,
+            stats,
+        }
+    }
+
+    /// Expand the next plan in the queue. Returns `false` when the search is over
+    pub fn step(
+        &mut self,
+        divider: &Divider,
+        order: &impl ActionOrder,
+        heuristic: &mut impl FnMut(State) -> f64,
+    ) -> bool {
+        let (mut plan, state, action) = match self.pending.pop_front() {
+            None => return false,
+            Some(entry) => entry,
+        };
+
+        let step_start = Instant::now();
+        tracing::debug!("Will apply {:?} to {}", action, plan.state(state));
+        let plan_mut = Rc::make_mut(&mut plan);
+        plan_mut.apply(state, action).unwrap();
+        tracing::trace!("Got new plan:\n{}", plan_mut);
+        tracing::trace!("Its cost is {}", plan_mut.symbolic_cost());
+        let cost_start = Instant::now();
+        let cost = plan_mut.objective_cost(self.objective, &mut *heuristic);
+        // Ties go to the plan with the fewest expected throws. Pruning the plans that cannot win
+        // them also bounds the search, as the other objectives may stop growing with the plan. The
+        // variance of a partial plan has no bound at all, so its search is bounded by the expected
+        // throws alone, with a tolerance for the rounding errors of plans as good as the best
+        let tie = self.objective != Objective::ExpectedThrows
+            && (cost.value - self.best_cost).abs() <= 1e-12;
+        let expected_pruned = match self.objective {
+            Objective::Variance => plan_mut.cost(&mut *heuristic).value > self.max_expected + 1e-9,
+            _ => tie && plan_mut.cost(&mut *heuristic).value > self.best_plan.exact_cost().unwrap(),
+        };
+        let cost_end = Instant::now();
+        tracing::debug!("Cost is {:?}", cost);
+
+        self.stats.generated += 1;
+        if !self.visited_plans.insert(plan.fingerprint()) {
+            self.stats.duplicates += 1;
+        } else if cost.value > self.best_cost && !tie || expected_pruned {
+            self.stats.pruned += 1;
+        } else {
+            self.stats.expanded += 1;
+            for (state, action) in plan.possible_actions(divider, order) {
+                self.pending.push_back((plan.clone(), state, action));
+            }
+        }
+
+        let step_end = Instant::now();
+        self.stats.peak_queue = self.stats.peak_queue.max(self.pending.len());
+        self.stats.peak_visited = self.stats.peak_visited.max(self.visited_plans.len());
+        self.stats.cost_time += cost_end - cost_start;
+        self.stats.expansion_time += (cost_start - step_start) + (step_end - cost_end);
+        self.stats.elapsed += step_end - step_start;
+
+        let better = match tie {
+            false => cost.value < self.best_cost,
+            true => plan.exact_cost() < self.best_plan.exact_cost(),
+        };
+        if !cost.estimated && better && !expected_pruned {
+            tracing::info!("Found better plan with cost {:?}", cost);
+            self.best_plan = plan;
+            self.best_cost = cost.value;
+            self.stats.improved(self.stats.elapsed, cost.value);
+        }
+
+        true
+    }
+
+    pub fn into_result(self) -> (Plan, SearchStats) {
+        let plan = Rc::try_unwrap(self.best_plan).unwrap_or_else(|plan| (*plan).clone());
+        (plan, self.stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_order::NaturalOrder;
+
+    #[test]
+    fn reproducible() {
+        let run = |start: State| {
+            let divider = Divider::new();
+            let mut search = BestSearch::new(
+                start,
+                &divider,
+                &NaturalOrder,
+                Objective::ExpectedThrows,
+                CostModel::default(),
+                &mut |_| 0.0,
+            );
+            let mut trace = Vec::new();
+            while search.step(&divider, &NaturalOrder, &mut |_| 0.0) {
+                trace.push((
+                    search.pending.len(),
+                    search.visited_plans.len(),
+                    search.best_cost.to_bits(),
+                ));
+            }
+            (trace, search.into_result().0.to_string())
+        };
+
+        for (source, target) in [(3, 2), (6, 4), (2, 5), (6, 5)] {
+            let start = State::new(source, target);
+            assert_eq!(run(start), run(start));
+        }
+    }
+
+    #[test]
+    fn cost_model() {
+        let start = State::new(2, 9);
+        let solve = |cost_model| {
+            let objective = Objective::ExpectedThrows;
+            best_solver(start, |_| 0.0, &NaturalOrder, objective, cost_model, None).0
+        };
+        let cost_model = CostModel {
+            throw: 1.0,
+            map: 0.0,
+            unit: 0.5,
+        };
+        let weighted = solve(cost_model);
+        let mut fewest_throws = solve(CostModel::default());
+        assert_eq!(weighted.cost_model(), cost_model);
+
+        // Large ranges cost more than the throws they save
+        fewest_throws.set_cost_model(cost_model);
+        assert!(weighted.exact_cost().unwrap() < fewest_throws.exact_cost().unwrap());
+        let mut naive = naive_solver(start);
+        naive.set_cost_model(cost_model);
+        assert!(weighted.exact_cost().unwrap() <= naive.exact_cost().unwrap());
+    }
+}
@@ -0,0 +1,468 @@
+use super::BestSearch;
+use crate::plan::{CostModel, Plan, PlanBranch, PlanFingerprint, StateId, StateInterner};
+use crate::search_stats::{Improvement, SearchStats};
+use crate::{Action, State};
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fs, io};
+
+const HEADER: &str = "cubique checkpoint v1";
+
+/// A file where a [`BestSearch`] is saved every `interval` iterations
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub path: PathBuf,
+    pub interval: u64,
+}
+
+impl Checkpoint {
+    pub fn new(path: impl Into<PathBuf>, interval: u64) -> Self {
+        Checkpoint {
+            path: path.into(),
+            interval,
+        }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Write to a temporary file first, so that an interrupted save does not destroy the previous
+    /// checkpoint
+    pub fn save(&self, search: &BestSearch) -> io::Result<()> {
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+
+        fs::write(&temp_path, encode(search))?;
+        fs::rename(&temp_path, &self.path)?;
+        tracing::info!("Saved checkpoint at iteration {}", search.stats.generated);
+        Ok(())
+    }
+
+    pub fn load(&self) -> io::Result<BestSearch> {
+        decode(&fs::read_to_string(&self.path)?)
+    }
+}
+
+/// Encode the search as text. States are written in the order of their ids and then referred to by
+/// id, plans shared by many queue entries are written only once, and floats are written by their
+/// bits, so that decoding gives back exactly the same search.
+fn encode(search: &BestSearch) -> String {
+    let mut plan_ids = HashMap::new();
+    let mut plans = Vec::new();
+    let mut out = String::new();
+
+    writeln!(out, "{}", HEADER).unwrap();
+    writeln!(
+        out,
+        "start {} {} {}",
+        search.start.source, search.start.target, search.start.units
+    )
+    .unwrap();
+    writeln!(out, "objective {}", search.objective).unwrap();
+    writeln!(out, "order {}", search.order).unwrap();
+    writeln!(out, "cost_model {}", search.best_plan.cost_model()).unwrap();
+
+    let stats = &search.stats;
+    writeln!(
+        out,
+        "counts {} {} {} {}",
+        stats.generated, stats.expanded, stats.duplicates, stats.pruned
+    )
+    .unwrap();
+    writeln!(out, "peaks {} {}", stats.peak_queue, stats.peak_visited).unwrap();
+    writeln!(
+        out,
+        "times {} {} {}",
+        stats.cost_time.as_nanos(),
+        stats.expansion_time.as_nanos(),
+        stats.elapsed.as_nanos()
+    )
+    .unwrap();
+    writeln!(out, "improvements {}", stats.improvements.len()).unwrap();
+    for improvement in &stats.improvements {
+        writeln!(
+            out,
+            "{} {:x}",
+            improvement.elapsed.as_nanos(),
+            improvement.cost.to_bits()
+        )
+        .unwrap();
+    }
+
+    let states = search.best_plan.interner().states();
+    writeln!(out, "states {}", states.len()).unwrap();
+    for state in states {
+        writeln!(out, "{} {}", state.units, state.target).unwrap();
+    }
+
+    writeln!(
+        out,
+        "best {} {:x} {:x}",
+        plan_id(&search.best_plan, &mut plan_ids, &mut plans),
+        search.best_cost.to_bits(),
+        search.max_expected.to_bits()
+    )
+    .unwrap();
+
+    writeln!(out, "pending {}", search.pending.len()).unwrap();
+    for (plan, state, action) in &search.pending {
+        let id = plan_id(plan, &mut plan_ids, &mut plans);
+        write!(out, "{} {} ", id, state.index()).unwrap();
+        match action {
+            Action::Throw => writeln!(out, "throw").unwrap(),
+            Action::Map(units) => writeln!(out, "map {}", units).unwrap(),
+        }
+    }
+
+    writeln!(out, "plans {}", plans.len()).unwrap();
+    for plan in plans {
+        writeln!(out, "plan {} {}", plan.start().index(), plan.plans().len()).unwrap();
+        for (id, branch) in plan.plans() {
+            write!(out, "{} ", id.index()).unwrap();
+            match *branch {
+                PlanBranch::Solved => writeln!(out, "solved").unwrap(),
+                PlanBranch::Library => writeln!(out, "library").unwrap(),
+                PlanBranch::Pending { min_map_units } => {
+                    writeln!(out, "pending {}", min_map_units).unwrap()
+                }
+                PlanBranch::Throw { next } => writeln!(out, "throw {}", next.index()).unwrap(),
+                PlanBranch::Map {
+                    units,
+                    sub_problem,
+                    remaining,
+                } => {
+                    write!(out, "map {} {}", units, sub_problem.index()).unwrap();
+                    if let Some(remaining) = remaining {
+                        write!(out, " {}", remaining.index()).unwrap();
+                    }
+                    writeln!(out).unwrap();
+                }
+            }
+        }
+    }
+
+    let mut visited_plans = search
+        .visited_plans
+        .iter()
+        .map(|fingerprint| fingerprint.description())
+        .collect::<Vec<_>>();
+    visited_plans.sort_unstable();
+    writeln!(out, "visited {}", visited_plans.len()).unwrap();
+    for description in visited_plans {
+        writeln!(out, "{}", description.iter().format(" ")).unwrap();
+    }
+
+    out
+}
+
+fn plan_id(
+    plan: &Rc<Plan>,
+    plan_ids: &mut HashMap<*const Plan, usize>,
+    plans: &mut Vec<Rc<Plan>>,
+) -> usize {
+    *plan_ids.entry(Rc::as_ptr(plan)).or_insert_with(|| {
+        plans.push(plan.clone());
+        plans.len() - 1
+    })
+}
+
+fn decode(text: &str) -> io::Result<BestSearch> {
+    let mut reader = Reader { rest: text };
+
+    if reader.line()? != HEADER {
+        return Err(invalid("not a checkpoint file"));
+    }
+
+    let start = match reader.fields("start")?[..] {
+        [source, target, units] => State {
+            source: number(source)?,
+            target: number(target)?,
+            units: number(units)?,
+        },
+        _ => return Err(invalid("bad start")),
+    };
+    let objective = match reader.fields("objective")?[..] {
+        [objective] => objective.parse().map_err(invalid)?,
+        _ => return Err(invalid("bad objective")),
+    };
+    let order = match reader.fields("order")?[..] {
+        [order] => order.to_string(),
+        _ => return Err(invalid("bad order")),
+    };
+    let cost_model: CostModel = match reader.fields("cost_model")?[..] {
+        [cost_model] => cost_model.parse().map_err(invalid)?,
+        _ => return Err(invalid("bad cost model")),
+    };
+
+    let mut stats = SearchStats::default();
+    match reader.fields("counts")?[..] {
+        [generated, expanded, duplicates, pruned] => {
+            stats.generated = number(generated)?;
+            stats.expanded = number(expanded)?;
+            stats.duplicates = number(duplicates)?;
+            stats.pruned = number(pruned)?;
+        }
+        _ => return Err(invalid("bad counts")),
+    }
+    match reader.fields("peaks")?[..] {
+        [queue, visited] => {
+            stats.peak_queue = number(queue)?;
+            stats.peak_visited = number(visited)?;
+        }
+        _ => return Err(invalid("bad peaks")),
+    }
+    match reader.fields("times")?[..] {
+        [cost, expansion, elapsed] => {
+            stats.cost_time = Duration::from_nanos(number(cost)?);
+            stats.expansion_time = Duration::from_nanos(number(expansion)?);
+            stats.elapsed = Duration::from_nanos(number(elapsed)?);
+        }
+        _ => return Err(invalid("bad times")),
+    }
+    for _ in 0..reader.count("improvements")? {
+        let line = reader.line()?;
+        let improvement = match line.split(' ').collect::<Vec<_>>()[..] {
+            [elapsed, cost] => Improvement {
+                elapsed: Duration::from_nanos(number(elapsed)?),
+                cost: float(cost)?,
+            },
+            _ => return Err(invalid(format!("bad improvement: {}", line))),
+        };
+        stats.improvements.push(improvement);
+    }
+
+    let interner = Arc::new(StateInterner::new());
+    let mut ids = Vec::new();
+    for _ in 0..reader.count("states")? {
+        let line = reader.line()?;
+        let state = match line.split(' ').collect::<Vec<_>>()[..] {
+            [units, target] => State {
+                source: start.source,
+                target: number(target)?,
+                units: number(units)?,
+            },
+            _ => return Err(invalid(format!("bad state: {}", line))),
+        };
+        ids.push(interner.intern(state));
+    }
+    let state = |id: &str| -> io::Result<StateId> {
+        let id = number::<usize>(id)?;
+        ids.get(id)
+            .copied()
+            .ok_or_else(|| invalid(format!("unknown state {}", id)))
+    };
+    let (best_plan_id, best_cost, max_expected) = match reader.fields("best")?[..] {
+        [id, cost, max_expected] => (number::<usize>(id)?, float(cost)?, float(max_expected)?),
+        _ => return Err(invalid("bad best")),
+    };
+
+    let mut pending = Vec::new();
+    for _ in 0..reader.count("pending")? {
+        let line = reader.line()?;
+        let entry = match line.split(' ').collect::<Vec<_>>()[..] {
+            [id, state_id, "throw"] => (number::<usize>(id)?, state(state_id)?, Action::Throw),
+            [id, state_id, "map", map_units] => (
+                number(id)?,
+                state(state_id)?,
+                Action::Map(number(map_units)?),
+            ),
+            _ => return Err(invalid(format!("bad pending entry: {}", line))),
+        };
+        pending.push(entry);
+    }
+
+    let mut plans = Vec::new();
+    for _ in 0..reader.count("plans")? {
+        let (plan_start, len) = match reader.fields("plan")?[..] {
+            [start, len] => (state(start)?, number(len)?),
+            _ => return Err(invalid("bad plan")),
+        };
+
+        let mut branches = Vec::new();
+        for _ in 0..len {
+            let line = reader.line()?;
+            let entry = match line.split(' ').collect::<Vec<_>>()[..] {
+                [id, "solved"] => (state(id)?, PlanBranch::Solved),
+                [id, "library"] => (state(id)?, PlanBranch::Library),
+                [id, "pending", min_map_units] => (
+                    state(id)?,
+                    PlanBranch::Pending {
+                        min_map_units: number(min_map_units)?,
+                    },
+                ),
+                [id, "throw", next] => (state(id)?, PlanBranch::Throw { next: state(next)? }),
+                [id, "map", map_units, sub_problem, ref remaining @ ..] => (
+                    state(id)?,
+                    PlanBranch::Map {
+                        units: number(map_units)?,
+                        sub_problem: state(sub_problem)?,
+                        remaining: match *remaining {
+                            [] => None,
+                            [remaining] => Some(state(remaining)?),
+                            _ => return Err(invalid(format!("bad plan entry: {}", line))),
+                        },
+                    },
+                ),
+                _ => return Err(invalid(format!("bad plan entry: {}", line))),
+            };
+            branches.push(entry);
+        }
+        let mut plan = Plan::from_branches(interner.clone(), plan_start, branches);
+        plan.set_cost_model(cost_model);
+        if let Err(problems) = plan.validate() {
+            return Err(invalid(format!(
+                "inconsistent plan: {}",
+                problems.iter().format(", ")
+            )));
+        }
+        plans.push(Rc::new(plan));
+    }
+
+    let plan = |id: usize| {
+        plans
+            .get(id)
+            .cloned()
+            .ok_or_else(|| invalid(format!("unknown plan {}", id)))
+    };
+
+    let mut visited_plans = HashSet::new();
+    for _ in 0..reader.count("visited")? {
+        let description = reader
+            .line()?
+            .split(' ')
+            .map(number)
+            .collect::<io::Result<_>>()?;
+        visited_plans.insert(PlanFingerprint::from_description(description));
+    }
+
+    Ok(BestSearch {
+        start,
+        objective,
+        order,
+        pending: pending
+            .into_iter()
+            .map(|(id, state, action)| Ok((plan(id)?, state, action)))
+            .collect::<io::Result<VecDeque<_>>>()?,
+        visited_plans,
+        best_plan: plan(best_plan_id)?,
+        best_cost,
+        max_expected,
+        stats,
+    })
+}
+
+struct Reader<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Reader<'a> {
+    fn line(&mut self) -> io::Result<&'a str> {
+        let (line, rest) = self
+            .rest
+            .split_once('\n')
+            .ok_or_else(|| invalid("unexpected end of file"))?;
+        self.rest = rest;
+        Ok(line)
+    }
+
+    /// Read a line starting with `keyword` and return the remaining space-separated fields
+    fn fields(&mut self, keyword: &str) -> io::Result<Vec<&'a str>> {
+        let mut fields = self.line()?.split(' ');
+        if fields.next() != Some(keyword) {
+            return Err(invalid(format!("expected {}", keyword)));
+        }
+        Ok(fields.collect())
+    }
+
+    fn count(&mut self, keyword: &str) -> io::Result<usize> {
+        match self.fields(keyword)?[..] {
+            [count] => number(count),
+            _ => Err(invalid(format!("bad {}", keyword))),
+        }
+    }
+}
+
+fn number<T: FromStr>(field: &str) -> io::Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    field.parse().map_err(invalid)
+}
+
+/// Read a float written by its bits
+fn float(field: &str) -> io::Result<f64> {
+    Ok(f64::from_bits(
+        u64::from_str_radix(field, 16).map_err(invalid)?,
+    ))
+}
+
+fn invalid(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_order::{ActionOrder, NaturalOrder};
+    use crate::divider::Divider;
+    use crate::plan::Objective;
+
+    #[test]
+    fn resume() {
+        let start = State::new(6, 4);
+        let divider = Divider::new();
+        let mut heuristic = |_| 0.0;
+
+        for objective in [Objective::ExpectedThrows, Objective::Variance] {
+            let mut new_search = || {
+                let cost_model = CostModel::default();
+                let order = &NaturalOrder;
+                BestSearch::new(
+                    start,
+                    &divider,
+                    order,
+                    objective,
+                    cost_model,
+                    &mut heuristic,
+                )
+            };
+            let mut uninterrupted = new_search();
+            let mut search = new_search();
+            while uninterrupted.step(&divider, &NaturalOrder, &mut heuristic) {}
+
+            for _ in 0..10 {
+                assert!(search.step(&divider, &NaturalOrder, &mut heuristic));
+            }
+            let encoded = encode(&search);
+            drop(search);
+
+            let mut resumed = decode(&encoded).unwrap();
+            assert_eq!(encode(&resumed), encoded);
+            assert_eq!(resumed.order, NaturalOrder.name());
+            assert_eq!(resumed.max_expected, uninterrupted.max_expected);
+            while resumed.step(&divider, &NaturalOrder, &mut heuristic) {}
+
+            assert_eq!(resumed.stats.generated, uninterrupted.stats.generated);
+            assert_eq!(resumed.stats.expanded, uninterrupted.stats.expanded);
+            assert_eq!(resumed.best_cost, uninterrupted.best_cost);
+            assert_eq!(
+                resumed.into_result().0.to_string(),
+                uninterrupted.into_result().0.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_checkpoint() {
+        assert!(decode("").is_err());
+        assert!(decode("something else\n").is_err());
+        assert!(decode(&format!("{}\nstart 6 4\n", HEADER)).is_err());
+    }
+}
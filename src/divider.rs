@@ -1,38 +1,70 @@
-#[derive(Debug)]
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Answers divisor queries for any number, caching the divisors of each number it was asked about.
+/// It is safe to share between threads
+#[derive(Debug, Default)]
 pub struct Divider {
-    all_divisors: Vec<Vec<u32>>,
+    cache: RwLock<HashMap<u32, Arc<[u32]>>>,
 }
 
 impl Divider {
-    pub fn new(target: u32) -> Self {
-        let mut all_divisors = Vec::with_capacity(target as usize + 1);
-        all_divisors.resize_with(target as usize + 1, Vec::new);
-
-        let mut main_divisors = Vec::new();
-        for d in 2..=target {
-            if target % d == 0 {
-                main_divisors.push(d);
-            }
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The divisors of `n` greater than 1, in increasing order. So `n` itself comes last, and there
+    /// are none for 1
+    pub fn divisors(&self, n: u32) -> Arc<[u32]> {
+        if let Some(divisors) = self.cache.read().unwrap().get(&n) {
+            return divisors.clone();
         }
 
-        for &n in &main_divisors {
-            let mut divisors = Vec::new();
-            for &d in &main_divisors {
-                if n % d == 0 {
-                    divisors.push(d);
-                }
+        let mut divisors = vec![1];
+        for (prime, exponent) in prime_factorization(n) {
+            let mut multiples = Vec::new();
+            let mut power = 1;
+            for _ in 0..exponent {
+                power *= prime;
+                multiples.extend(divisors.iter().map(|&divisor| divisor * power));
             }
-            all_divisors[n as usize] = divisors;
+            divisors.extend(multiples);
         }
+        divisors.sort_unstable();
+        divisors.remove(0);
 
-        all_divisors[target as usize] = main_divisors;
+        self.cache
+            .write()
+            .unwrap()
+            .entry(n)
+            .or_insert_with(|| divisors.into())
+            .clone()
+    }
+}
+
+/// The prime factors of `n` in increasing order, with their exponents
+pub fn prime_factorization(mut n: u32) -> Vec<(u32, u32)> {
+    assert!(n > 0, "0 has no prime factorization");
+    let mut factors = Vec::new();
 
-        Divider { all_divisors }
+    let mut prime = 2;
+    while prime <= n / prime {
+        if n % prime == 0 {
+            let mut exponent = 0;
+            while n % prime == 0 {
+                n /= prime;
+                exponent += 1;
+            }
+            factors.push((prime, exponent));
+        }
+        prime += if prime == 2 { 1 } else { 2 };
     }
 
-    pub fn divisors(&self, n: u32) -> &[u32] {
-        &self.all_divisors[n as usize]
+    if n > 1 {
+        factors.push((n, 1));
     }
+
+    factors
 }
 
 #[cfg(test)]
@@ -41,21 +73,40 @@ mod tests {
 
     #[test]
     fn test() {
-        let divider = Divider::new(2 * 2 * 3 * 7);
+        let divider = Divider::new();
 
         assert_eq!(
-            divider.divisors(84),
+            &*divider.divisors(84),
             &[2, 3, 4, 6, 7, 12, 14, 21, 28, 42, 84]
         );
-        assert_eq!(divider.divisors(42), &[2, 3, 6, 7, 14, 21, 42]);
-        assert_eq!(divider.divisors(28), &[2, 4, 7, 14, 28]);
-        assert_eq!(divider.divisors(21), &[3, 7, 21]);
-        assert_eq!(divider.divisors(14), &[2, 7, 14]);
-        assert_eq!(divider.divisors(12), &[2, 3, 4, 6, 12]);
-        assert_eq!(divider.divisors(7), &[7]);
-        assert_eq!(divider.divisors(6), &[2, 3, 6]);
-        assert_eq!(divider.divisors(4), &[2, 4]);
-        assert_eq!(divider.divisors(3), &[3]);
-        assert_eq!(divider.divisors(2), &[2]);
+        assert_eq!(&*divider.divisors(42), &[2, 3, 6, 7, 14, 21, 42]);
+        assert_eq!(&*divider.divisors(28), &[2, 4, 7, 14, 28]);
+        assert_eq!(&*divider.divisors(21), &[3, 7, 21]);
+        assert_eq!(&*divider.divisors(14), &[2, 7, 14]);
+        assert_eq!(&*divider.divisors(12), &[2, 3, 4, 6, 12]);
+        assert_eq!(&*divider.divisors(7), &[7]);
+        assert_eq!(&*divider.divisors(6), &[2, 3, 6]);
+        assert_eq!(&*divider.divisors(4), &[2, 4]);
+        assert_eq!(&*divider.divisors(3), &[3]);
+        assert_eq!(&*divider.divisors(2), &[2]);
+        assert_eq!(&*divider.divisors(1), &[]);
+    }
+
+    #[test]
+    fn large_numbers() {
+        let divider = Divider::new();
+
+        assert_eq!(
+            prime_factorization(735_134_400),
+            [(2, 6), (3, 3), (5, 2), (7, 1), (11, 1), (13, 1), (17, 1)]
+        );
+        let divisors = divider.divisors(735_134_400);
+        assert_eq!(divisors.len(), 1343);
+        assert!(divisors.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(divisors.iter().all(|&d| 735_134_400 % d == 0));
+
+        assert_eq!(prime_factorization(4_294_967_291), [(4_294_967_291, 1)]);
+        assert_eq!(&*divider.divisors(4_294_967_291), &[4_294_967_291]);
+        assert_eq!(prime_factorization(65_521 * 65_521), [(65_521, 2)]);
     }
 }
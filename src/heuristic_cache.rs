@@ -1,23 +1,29 @@
-use crate::plan::Plan;
+use crate::plan::{Plan, StateId, StateInterner};
 use crate::State;
-use std::collections::HashMap;
+use std::sync::Arc;
 
 pub struct HeuristicCache<S> {
-    cache: HashMap<State, f64>,
+    interner: Arc<StateInterner>,
+    /// Indexed by state id
+    cache: Vec<Option<f64>>,
     solver: S,
 }
 
 impl<S: FnMut(State) -> Plan> HeuristicCache<S> {
-    pub fn new(solver: S) -> Self {
+    pub fn new(interner: Arc<StateInterner>, solver: S) -> Self {
         HeuristicCache {
-            cache: Default::default(),
+            interner,
+            cache: Vec::new(),
             solver,
         }
     }
 
-    pub fn calculate(&mut self, state: State) -> f64 {
-        *self.cache.entry(state).or_insert_with(|| {
-            (self.solver)(state)
+    pub fn calculate(&mut self, id: StateId) -> f64 {
+        if id.index() >= self.cache.len() {
+            self.cache.resize(id.index() + 1, None);
+        }
+        *self.cache[id.index()].get_or_insert_with(|| {
+            (self.solver)(self.interner.state(id))
                 .exact_cost()
                 .expect("The solver must returned a solved plan")
         })
@@ -30,8 +36,12 @@ mod tests {
 
     #[test]
     fn test() {
+        let interner = Arc::new(StateInterner::new());
+        let one = interner.intern(State::new(1, 1));
+        let two = interner.intern(State::new(2, 1));
+
         let mut i = 0;
-        let mut cache = HeuristicCache::new(|state| {
+        let mut cache = HeuristicCache::new(interner, |state| {
             if i == 0 {
                 assert_eq!(state.source, 1);
             } else if i == 1 {
@@ -44,9 +54,9 @@ mod tests {
             Plan::new(state)
         });
 
-        assert_eq!(cache.calculate(State::new(1, 1)), 0.0);
-        assert_eq!(cache.calculate(State::new(2, 1)), 0.0);
-        assert_eq!(cache.calculate(State::new(1, 1)), 0.0);
-        assert_eq!(cache.calculate(State::new(2, 1)), 0.0);
+        assert_eq!(cache.calculate(one), 0.0);
+        assert_eq!(cache.calculate(two), 0.0);
+        assert_eq!(cache.calculate(one), 0.0);
+        assert_eq!(cache.calculate(two), 0.0);
     }
 }
@@ -0,0 +1,243 @@
+use crate::action_order::ActionOrder;
+use crate::divider::Divider;
+use crate::naive_solver;
+use crate::plan::{CostModel, Plan, UndoLog};
+use crate::search_stats::SearchStats;
+use crate::State;
+use std::time::{Duration, Instant};
+
+/// A memory-bounded alternative to [`crate::best_solver::best_solver`], using iterative deepening
+/// on the cost bound (IDA*).
+///
+/// Each iteration is a depth-first search that edits a single plan in place and reverts each action
+/// when backtracking, so the memory grows only linearly with the depth of the plans. The pending
+/// states are always decided in the same order, so that each plan is reached only once and no
+/// visited set is needed. The order of the actions decides which complete plans are found first
+/// within an iteration, and so how much is pruned.
+///
+/// With an admissible heuristic, the first iteration that finds a complete plan finds an optimal
+/// one.
+///
+/// In the statistics, the queue is the stack of the depth-first search, and there is no visited set.
+pub fn ida_solver(
+    start: State,
+    mut heuristic: impl FnMut(State) -> f64,
+    order: &impl ActionOrder,
+    cost_model: CostModel,
+) -> (Plan, SearchStats) {
+    let search_start = Instant::now();
+    let divider = Divider::new();
+    let mut naive_plan = naive_solver(start);
+    naive_plan.set_cost_model(cost_model);
+    let naive_cost = naive_plan.exact_cost().unwrap();
+    tracing::info!("Initial cost is {:?}", naive_cost);
+
+    let mut stats = SearchStats::default();
+    stats.improved(Duration::ZERO, naive_cost);
+
+    let mut plan = Plan::new(start);
+    plan.set_cost_model(cost_model);
+    // Backtracking reverts each action exactly, which never leaves unreachable states
+    plan.set_auto_prune(false);
+    let mut log = UndoLog::new();
+    let mut iteration = IdaIteration {
+        threshold: plan.cost(&mut heuristic).value,
+        next_threshold: f64::INFINITY,
+        best: None,
+        visited: 0,
+        depth: 0,
+        search_start,
+        stats,
+    };
+
+    loop {
+        tracing::info!("Searching plans with cost up to {:?}", iteration.threshold);
+        iteration.visit(&mut plan, &mut log, &divider, order, &mut heuristic);
+        tracing::info!("Visited {} plans", iteration.visited);
+
+        let mut stats = iteration.stats;
+        stats.elapsed = search_start.elapsed();
+
+        if let Some((cost, mut best)) = iteration.best {
+            tracing::info!("Found best plan with cost {:?}", cost);
+            best.set_auto_prune(true);
+            return (best, stats);
+        }
+
+        if iteration.next_threshold >= naive_cost {
+            return (naive_plan, stats);
+        }
+
+        iteration = IdaIteration {
+            threshold: iteration.next_threshold,
+            next_threshold: f64::INFINITY,
+            best: None,
+            visited: 0,
+            depth: 0,
+            search_start,
+            stats,
+        };
+    }
+}
+
+struct IdaIteration {
+    threshold: f64,
+    /// The smallest cost above the threshold, which will be the threshold of the next iteration
+    next_threshold: f64,
+    best: Option<(f64, Plan)>,
+    visited: u64,
+    depth: usize,
+    search_start: Instant,
+    /// Accumulated over all iterations
+    stats: SearchStats,
+}
+
+impl IdaIteration {
+    fn visit(
+        &mut self,
+        plan: &mut Plan,
+        log: &mut UndoLog,
+        divider: &Divider,
+        order: &impl ActionOrder,
+        heuristic: &mut impl FnMut(State) -> f64,
+    ) {
+        self.visited += 1;
+        let cost_start = Instant::now();
+        let cost = plan.cost(&mut *heuristic);
+        self.stats.cost_time += cost_start.elapsed();
+
+        if cost.value > self.threshold {
+            self.next_threshold = self.next_threshold.min(cost.value);
+            self.stats.pruned += 1;
+            return;
+        }
+
+        if let Some((best_cost, _)) = self.best {
+            if cost.value >= best_cost {
+                self.stats.pruned += 1;
+                return;
+            }
+        }
+
+        let expansion_start = Instant::now();
+        match plan.first_pending_actions(divider, order) {
+            None => {
+                tracing::debug!("Found plan with cost {:?}", cost);
+                self.best = Some((cost.value, plan.clone()));
+
+                let incumbent = self.stats.improvements.last().unwrap().cost;
+                if cost.value < incumbent {
+                    self.stats.improved(self.search_start.elapsed(), cost.value);
+                }
+            }
+            Some((state, actions)) => {
+                self.stats.expanded += 1;
+                self.depth += 1;
+                self.stats.peak_queue = self.stats.peak_queue.max(self.depth);
+                self.stats.expansion_time += expansion_start.elapsed();
+
+                let before = log.len();
+                for action in actions {
+                    let apply_start = Instant::now();
+                    log.push(plan.apply_undoable(state, action).unwrap());
+                    self.stats.generated += 1;
+                    self.stats.expansion_time += apply_start.elapsed();
+
+                    self.visit(plan, log, divider, order, heuristic);
+
+                    let undo_start = Instant::now();
+                    log.rollback(plan, before);
+                    self.stats.expansion_time += undo_start.elapsed();
+                }
+                self.depth -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_order::{GreedyOrder, LargestMapFirst, LearnedOrder, NaturalOrder};
+    use crate::plan::PlanLibrary;
+    use crate::test_util;
+    use std::sync::Arc;
+
+    #[test]
+    fn same_cost_as_best_solver() {
+        for (source, target) in [(3, 2), (2, 3), (6, 4), (2, 5), (6, 5)] {
+            let start = State::new(source, target);
+            let best = test_util::best(start);
+            let expected = best.exact_cost().unwrap();
+            let mut learned = LearnedOrder::new();
+            learned.learn(&best);
+
+            let costs = [
+                ida_solver(start, |_| 0.0, &NaturalOrder, CostModel::default())
+                    .0
+                    .exact_cost(),
+                ida_solver(start, |_| 0.0, &LargestMapFirst, CostModel::default())
+                    .0
+                    .exact_cost(),
+                ida_solver(
+                    start,
+                    |_| 0.0,
+                    &GreedyOrder::new(|_| 0.0),
+                    CostModel::default(),
+                )
+                .0
+                .exact_cost(),
+                ida_solver(start, |_| 0.0, &learned, CostModel::default())
+                    .0
+                    .exact_cost(),
+            ];
+            for cost in costs {
+                let cost = cost.unwrap();
+                assert!(
+                    (cost - expected).abs() < 1e-12,
+                    "{}/{}: {} != {}",
+                    source,
+                    target,
+                    cost,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn result_prunes_unreachable_states() {
+        let (mut plan, _) = ida_solver(
+            State::new(6, 4),
+            |_| 0.0,
+            &NaturalOrder,
+            CostModel::default(),
+        );
+        let library = Arc::new(PlanLibrary::new(CostModel::default()));
+        library.insert(naive_solver(State::new(6, 2)));
+
+        // Referring to the library leaves the states of the sub-problem unreachable
+        plan.use_library(library);
+        assert_eq!(plan.plans().len(), plan.reachable_states().len());
+    }
+
+    #[test]
+    fn undo_restores_plan() {
+        let start = State::new(6, 4);
+        let divider = Divider::new();
+        let mut plan = Plan::new(start);
+
+        while let Some((state, actions)) = plan.first_pending_actions(&divider, &NaturalOrder) {
+            let before = plan.to_string();
+            let undo = plan
+                .apply_undoable(state, *actions.last().unwrap())
+                .unwrap();
+            let after = plan.clone();
+
+            plan.undo(undo);
+            assert_eq!(plan.to_string(), before);
+
+            plan = after;
+        }
+    }
+}
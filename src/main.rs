@@ -1,25 +1,38 @@
+mod action_order;
+mod best_solver;
 mod divider;
 mod heuristic_cache;
+mod ida_solver;
+mod parallel_solver;
+mod pareto_solver;
+mod persistent_map;
 mod plan;
+mod search_stats;
+#[cfg(test)]
+mod test_util;
 
-use crate::divider::Divider;
+use crate::action_order::{ActionOrder, GreedyOrder, LargestMapFirst, LearnedOrder, NaturalOrder};
+use crate::best_solver::{best_solver, Checkpoint};
+use crate::divider::{prime_factorization, Divider};
 use crate::heuristic_cache::HeuristicCache;
-use itertools::Itertools;
-use plan::{Plan, PlanBranch};
-use std::collections::{HashSet, VecDeque};
+use crate::ida_solver::ida_solver;
+use crate::parallel_solver::parallel_solver;
+use crate::pareto_solver::pareto_solver;
+use crate::search_stats::SearchStats;
+use plan::{CostModel, Objective, Plan, PlanBranch, PlanLibrary};
+use std::env;
 use std::fmt::{Display, Formatter};
-use std::fs;
-use std::rc::Rc;
+use std::sync::Arc;
 use tracing::Level;
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct State {
     source: u32,
     target: u32,
     units: u32,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Action {
     /// Throw one more time
     Throw,
@@ -42,87 +55,247 @@ impl State {
 }
 
 fn main() {
-    tracing_subscriber::fmt().with_max_level(Level::INFO).init();
+    let mut source = 6;
+    let mut target = 8;
+    let mut checkpoint = None;
+    let mut threads = None;
+    let mut memory_bounded = false;
+    let mut factorized = false;
+    let mut library = false;
+    let mut via = None;
+    let mut pareto = false;
+    let mut sensitivity = false;
+    let mut breakdown = false;
+    let mut distribution = None;
+    let mut deterministic = false;
+    let mut stats_format = None;
+    let mut order = String::from("natural");
+    let mut objective = Objective::ExpectedThrows;
+    let mut cost_model = CostModel::default();
 
-    let start = State::new(6, 8);
-    let mut heuristic = HeuristicCache::new(naive_solver);
-    let best = best_solver(start, |_| 0.0);
-    let heuristic_cost = heuristic.calculate(start);
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--source" => source = args.next().unwrap().parse().unwrap(),
+            "--target" => target = args.next().unwrap().parse().unwrap(),
+            "--checkpoint" => checkpoint = Some(Checkpoint::new(args.next().unwrap(), 100_000)),
+            "--threads" => threads = Some(args.next().unwrap().parse().unwrap()),
+            "--memory-bounded" => memory_bounded = true,
+            "--factorized" => factorized = true,
+            "--library" => library = true,
+            "--via" => via = Some(args.next().unwrap().parse().unwrap()),
+            "--pareto" => pareto = true,
+            "--sensitivity" => sensitivity = true,
+            "--breakdown" => breakdown = true,
+            "--distribution" => distribution = Some(args.next().unwrap().parse().unwrap()),
+            "--deterministic" => deterministic = true,
+            "--stats" => {
+                let format = args.next().unwrap();
+                assert!(
+                    matches!(format.as_str(), "table" | "json"),
+                    "Unknown statistics format {}",
+                    format
+                );
+                stats_format = Some(format);
+            }
+            "--order" => order = args.next().unwrap(),
+            "--objective" => objective = args.next().unwrap().parse().unwrap(),
+            "--cost-model" => cost_model = args.next().unwrap().parse().unwrap(),
+            _ => panic!("Unknown argument {}", arg),
+        }
+    }
+
+    // Without timestamps, two runs with the same arguments give byte-identical outputs
+    let subscriber = tracing_subscriber::fmt().with_max_level(Level::INFO);
+    if deterministic {
+        assert!(
+            threads.is_none() || memory_bounded,
+            "The logs of a multi-threaded search are not deterministic"
+        );
+        subscriber.without_time().init();
+    } else {
+        subscriber.init();
+    }
+
+    assert!(
+        checkpoint.is_none()
+            || !(factorized || library || via.is_some() || threads.is_some() || memory_bounded),
+        "A checkpoint is for a single search"
+    );
+    assert!(
+        via.is_none() || !(factorized || library),
+        "A plan throwing a built die is not combined with others"
+    );
+    assert!(
+        objective == Objective::ExpectedThrows || threads.is_none() && !memory_bounded,
+        "Only the default solver optimizes other objectives"
+    );
+    assert!(
+        !pareto
+            || threads.is_none()
+                && checkpoint.is_none()
+                && objective == Objective::ExpectedThrows
+                && !(memory_bounded || factorized || library || via.is_some()),
+        "The Pareto search is a search of its own"
+    );
+    let start = State::new(source, target);
+    let order: Box<dyn ActionOrder + Sync> = match order.as_str() {
+        "natural" => Box::new(NaturalOrder),
+        "largest-map" => Box::new(LargestMapFirst),
+        "greedy" => Box::new(GreedyOrder::new(|_| 0.0)),
+        "learned" => Box::new(learn_from_sub_problems(start, cost_model)),
+        _ => panic!("Unknown action order {}", order),
+    };
+    if pareto {
+        let (frontier, stats) = pareto_solver(start, |_| 0.0, &order, cost_model);
+        for point in &frontier {
+            println!("{}", point.plan);
+            println!(
+                "Cost = {}, {} states, {} maps\n",
+                point.cost, point.size, point.maps
+            );
+        }
+        print_stats(&stats, stats_format.as_deref());
+        return;
+    }
+    let solve = |start| match threads {
+        _ if memory_bounded => ida_solver(start, |_| 0.0, &order, cost_model),
+        Some(threads) => parallel_solver(start, |_| 0.0, &order, cost_model, threads),
+        None => best_solver(
+            start,
+            |_| 0.0,
+            &order,
+            objective,
+            cost_model,
+            checkpoint.as_ref(),
+        ),
+    };
+    // Build a die with `via` faces from the source, and throw it instead
+    if let Some(via) = via {
+        let (die, die_stats) = solve(State::new(source, via));
+        let (plan, plan_stats) = solve(State::new(via, target));
+        let sequence = Plan::sequence(&die, &plan);
+        println!("{}", sequence);
+        println!("Cost = {}", sequence.exact_cost().unwrap());
+        print_stats(&die_stats, stats_format.as_deref());
+        print_stats(&plan_stats, stats_format.as_deref());
+        return;
+    }
+    // Solve each prime power of the target alone and combine the plans, which is much faster but
+    // may miss the best plan
+    let solutions = if factorized && target > 1 {
+        prime_factorization(target)
+            .into_iter()
+            .map(|(prime, exponent)| solve(State::new(source, prime.pow(exponent))))
+            .collect()
+    } else {
+        vec![solve(start)]
+    };
+    let mut best = solutions
+        .iter()
+        .map(|(plan, _)| plan.clone())
+        .reduce(|a, b| Plan::product(&a, &b))
+        .unwrap();
+    // Solve the sub-problems alone, so that the plan refers to them instead of repeating them
+    if library {
+        let library = Arc::new(PlanLibrary::new(cost_model));
+        for &target in Divider::new().divisors(target).iter() {
+            if target < start.target {
+                library.insert(solve(State::new(source, target)).0);
+            }
+        }
+        best.use_library(library);
+    }
+    let mut heuristic = HeuristicCache::new(best.interner().clone(), |state| {
+        let mut plan = naive_solver(state);
+        plan.set_cost_model(cost_model);
+        plan
+    });
+    let heuristic_cost = heuristic.calculate(best.start());
     let cost = best.exact_cost().unwrap();
     println!("{}", best);
     println!("Heuristic cost = {}", heuristic_cost);
     println!("Cost = {}", cost);
-}
-
-fn naive_solver(state: State) -> Plan {
-    tracing::debug!("Naive solver for {}", state);
-    let mut plan = Plan::new(state);
+    if objective != Objective::ExpectedThrows {
+        let value = best.objective_cost(objective, |_| 0.0).value;
+        println!("Objective {} = {}", objective, value);
+    }
+    if breakdown {
+        print!("{}", best.cost_breakdown(|_| 0.0));
+    }
+    if let Some(horizon) = distribution {
+        print!("{}", best.throw_distribution(horizon).unwrap());
+    }
+    // Compare with the plans that trade throws for fewer states or maps
+    if sensitivity {
+        let (frontier, _) = pareto_solver(start, |_| 0.0, &order, cost_model);
+        let alternatives = frontier
+            .into_iter()
+            .map(|point| point.plan)
+            .collect::<Vec<_>>();
+        print!("{}", best.sensitivity(&alternatives).unwrap());
+    }
 
-    while let Some((&pending, _)) = plan
-        .plans()
-        .iter()
-        .find(|(_, plan)| matches!(plan, PlanBranch::Pending { .. }))
-    {
-        if pending.units < pending.target {
-            plan.apply(pending, Action::Throw).unwrap();
-        } else {
-            plan.apply(pending, Action::Map(pending.target)).unwrap();
+    for (plan, stats) in &solutions {
+        if factorized && stats_format.as_deref() == Some("table") {
+            println!("Search for {}", plan.state(plan.start()));
         }
+        print_stats(stats, stats_format.as_deref());
     }
-
-    plan
 }
 
-fn best_solver(start: State, mut heuristic: impl FnMut(State) -> f64) -> Plan {
-    let mut visited_plans = HashSet::new();
-    let divider = Divider::new(start.target);
-    let mut pending = VecDeque::new();
-
-    let base_plan = Rc::new(Plan::new(start));
-    for (state, action) in base_plan.possible_actions(&divider) {
-        pending.push_back((base_plan.clone(), state, action));
+fn print_stats(stats: &SearchStats, format: Option<&str>) {
+    match format {
+        Some("table") => print!("{}", stats),
+        Some("json") => println!("{}", stats.to_json()),
+        _ => {}
     }
+}
 
-    let mut best_plan = Rc::new(naive_solver(start));
-    let mut best_cost = best_plan.exact_cost().unwrap();
-    tracing::info!("Initial cost is {:?}", best_cost);
-
-    let mut i = 0;
-    while let Some((mut plan, state, action)) = pending.pop_front() {
-        tracing::debug!("Will apply {:?} to {}", action, state);
-        Rc::make_mut(&mut plan).apply(state, action).unwrap();
-        tracing::trace!("Got new plan:\n{}", plan);
-        let cost = plan.cost(&mut heuristic);
-        tracing::debug!("Cost is {:?}", cost);
-
-        if visited_plans.insert(plan.to_string()) && cost.value <= best_cost {
-            for (state, action) in plan.possible_actions(&divider) {
-                pending.push_back((plan.clone(), state, action));
-            }
-        }
+/// Solve the problems with a smaller target that divides the target of `start`, from the smallest,
+/// each with what was learned from the previous ones. Their states are also states of the problem
+fn learn_from_sub_problems(start: State, cost_model: CostModel) -> LearnedOrder {
+    let mut learned = LearnedOrder::new();
+    let divider = Divider::new();
 
-        if !cost.estimated && cost.value < best_cost {
-            tracing::info!("Found better plan with cost {:?}", cost);
-            best_plan = plan.clone();
-            best_cost = cost.value;
+    for &target in divider.divisors(start.target).iter() {
+        if target < start.target {
+            let (plan, _) = best_solver(
+                State::new(start.source, target),
+                |_| 0.0,
+                &learned,
+                Objective::ExpectedThrows,
+                cost_model,
+                None,
+            );
+            learned.learn(&plan);
         }
+    }
 
-        i += 1;
+    learned
+}
 
-        if i % 100_000 == 0 {
-            tracing::info!(
-                "Iteration {}: queue size is {}, visited {} plans",
-                i,
-                pending.len(),
-                visited_plans.len()
-            );
+fn naive_solver(state: State) -> Plan {
+    tracing::debug!("Naive solver for {}", state);
+    let mut plan = Plan::new(state);
+    complete_naively(&mut plan);
+    plan
+}
 
-            fs::write("plans.txt", visited_plans.iter().format("\n\n").to_string()).unwrap();
-            panic!("done");
+/// Decide all pending states the way [`naive_solver`] does
+fn complete_naively(plan: &mut Plan) {
+    while let Some((&id, _)) = plan
+        .plans()
+        .iter()
+        .find(|(_, plan)| matches!(plan, PlanBranch::Pending { .. }))
+    {
+        let pending = plan.state(id);
+        if pending.units < pending.target {
+            plan.apply(id, Action::Throw).unwrap();
+        } else {
+            plan.apply(id, Action::Map(pending.target)).unwrap();
         }
     }
-
-    Rc::try_unwrap(best_plan).unwrap()
 }
 
 impl Display for State {
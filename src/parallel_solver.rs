@@ -0,0 +1,296 @@
+use crate::action_order::ActionOrder;
+use crate::divider::Divider;
+use crate::naive_solver;
+use crate::plan::{CostModel, Plan, PlanFingerprint, StateId};
+use crate::search_stats::{Improvement, SearchStats};
+use crate::{Action, State};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How many queue entries a worker takes at once
+const BATCH_SIZE: usize = 64;
+
+const VISITED_SHARDS: usize = 64;
+
+/// How much more than the best plan so far a plan may cost without being pruned
+const COST_TOLERANCE: f64 = 1e-9;
+
+type QueueEntry = (Arc<Plan>, StateId, Action);
+
+/// A multi-threaded version of [`crate::best_solver::best_solver`].
+///
+/// The queue is expanded level by level, with the entries of each level split among the threads.
+/// All threads share the visited plans and the cost of the best plan so far, used for pruning.
+///
+/// With an admissible heuristic, every optimal plan is found whatever the timing of the threads,
+/// so the ties between them are broken by their textual representation. Plans are only pruned when
+/// they cost more than the bound by a tolerance, so that an optimal plan whose cost is rounded a
+/// little above the bound is not pruned or kept depending on when the bound tightens. This makes the
+/// result independent of the number of threads. The logs are not: how much is pruned at each depth
+/// depends on when the threads find better plans.
+///
+/// In the statistics, the cost and expansion times are summed over all threads.
+pub fn parallel_solver(
+    start: State,
+    heuristic: impl Fn(State) -> f64 + Sync,
+    order: &(impl ActionOrder + Sync),
+    cost_model: CostModel,
+    threads: usize,
+) -> (Plan, SearchStats) {
+    assert!(threads > 0, "At least one thread is needed");
+    let search_start = Instant::now();
+    let mut base_plan = Plan::new(start);
+    base_plan.set_cost_model(cost_model);
+    let mut naive_plan = base_plan.clone();
+    naive_plan.complete_with(naive_solver);
+    let search = ParallelSearch {
+        divider: Divider::new(),
+        heuristic,
+        order,
+        visited_plans: VisitedPlans::new(),
+        incumbent: Incumbent::new(naive_plan, search_start),
+    };
+    let mut stats = SearchStats::default();
+    tracing::info!("Initial cost is {:?}", search.incumbent.bound());
+
+    let base_plan = Arc::new(base_plan);
+    let mut level = base_plan
+        .possible_actions(&search.divider, order)
+        .into_iter()
+        .map(|(state, action)| (base_plan.clone(), state, action))
+        .collect::<Vec<_>>();
+    let mut depth = 0;
+
+    while !level.is_empty() {
+        depth += 1;
+        let visited = search.visited_plans.len();
+        tracing::info!(
+            "Depth {}: queue size is {}, visited {} plans",
+            depth,
+            level.len(),
+            visited
+        );
+        stats.peak_queue = stats.peak_queue.max(level.len());
+        stats.peak_visited = stats.peak_visited.max(visited);
+
+        let next_batch = AtomicUsize::new(0);
+        let mut batches = thread::scope(|scope| {
+            let mut workers = Vec::with_capacity(threads);
+            for _ in 0..threads {
+                workers.push(scope.spawn(|| search.work(&level, &next_batch)));
+            }
+
+            let mut batches = Vec::new();
+            for worker in workers {
+                let (worker_batches, worker_stats) = worker.join().unwrap();
+                batches.extend(worker_batches);
+                stats.generated += worker_stats.generated;
+                stats.expanded += worker_stats.expanded;
+                stats.duplicates += worker_stats.duplicates;
+                stats.pruned += worker_stats.pruned;
+                stats.cost_time += worker_stats.cost_time;
+                stats.expansion_time += worker_stats.expansion_time;
+            }
+            batches
+        });
+
+        // Keep the queue in the same order as a single-threaded search would
+        batches.sort_unstable_by_key(|&(batch, _)| batch);
+        level = batches
+            .into_iter()
+            .flat_map(|(_, next_level)| next_level)
+            .collect();
+    }
+
+    stats.peak_visited = stats.peak_visited.max(search.visited_plans.len());
+    stats.elapsed = search_start.elapsed();
+    let (plan, improvements) = search.incumbent.into_result();
+    stats.improvements = improvements;
+    (plan, stats)
+}
+
+/// What is shared by all threads
+struct ParallelSearch<'a, H, O> {
+    divider: Divider,
+    heuristic: H,
+    order: &'a O,
+    visited_plans: VisitedPlans,
+    incumbent: Incumbent,
+}
+
+impl<H: Fn(State) -> f64 + Sync, O: ActionOrder + Sync> ParallelSearch<'_, H, O> {
+    /// Expand batches of the level until there are none left, returning the next level entries of
+    /// each batch, and the statistics of this thread
+    fn work(
+        &self,
+        level: &[QueueEntry],
+        next_batch: &AtomicUsize,
+    ) -> (Vec<(usize, Vec<QueueEntry>)>, SearchStats) {
+        let mut batches = Vec::new();
+        let mut stats = SearchStats::default();
+
+        loop {
+            let batch = next_batch.fetch_add(1, Ordering::Relaxed);
+            let from = batch * BATCH_SIZE;
+            if from >= level.len() {
+                return (batches, stats);
+            }
+
+            let mut next_level = Vec::new();
+            for (plan, state, action) in &level[from..level.len().min(from + BATCH_SIZE)] {
+                self.expand(plan.clone(), *state, *action, &mut next_level, &mut stats);
+            }
+            batches.push((batch, next_level));
+        }
+    }
+
+    fn expand(
+        &self,
+        mut plan: Arc<Plan>,
+        state: StateId,
+        action: Action,
+        next_level: &mut Vec<QueueEntry>,
+        stats: &mut SearchStats,
+    ) {
+        let expansion_start = Instant::now();
+        tracing::debug!("Will apply {:?} to {}", action, plan.state(state));
+        let plan_mut = Arc::make_mut(&mut plan);
+        plan_mut.apply(state, action).unwrap();
+        let cost_start = Instant::now();
+        let cost = plan_mut.cost(&self.heuristic);
+        let cost_end = Instant::now();
+        tracing::debug!("Cost is {:?}", cost);
+
+        stats.generated += 1;
+        if !self.visited_plans.insert(plan.fingerprint()) {
+            stats.duplicates += 1;
+        } else if cost.value > self.incumbent.bound() + COST_TOLERANCE {
+            stats.pruned += 1;
+        } else {
+            stats.expanded += 1;
+            for (state, action) in plan.possible_actions(&self.divider, self.order) {
+                next_level.push((plan.clone(), state, action));
+            }
+        }
+        stats.cost_time += cost_end - cost_start;
+        stats.expansion_time += (cost_start - expansion_start) + cost_end.elapsed();
+
+        if !cost.estimated {
+            self.incumbent.offer(cost.value, plan);
+        }
+    }
+}
+
+/// A set of plan fingerprints, split in shards to reduce the contention between threads
+struct VisitedPlans {
+    shards: Vec<Mutex<HashSet<PlanFingerprint>>>,
+}
+
+impl VisitedPlans {
+    fn new() -> Self {
+        VisitedPlans {
+            shards: (0..VISITED_SHARDS).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    fn insert(&self, plan: PlanFingerprint) -> bool {
+        let mut hasher = DefaultHasher::new();
+        plan.hash(&mut hasher);
+        let shard = hasher.finish() as usize % self.shards.len();
+
+        self.shards[shard].lock().unwrap().insert(plan)
+    }
+
+    fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+}
+
+/// The best complete plan found so far. Its cost is also kept in an atomic, so that threads can
+/// read the pruning bound without locking
+struct Incumbent {
+    cost_bits: AtomicU64,
+    best: Mutex<(f64, String, Arc<Plan>)>,
+    search_start: Instant,
+    improvements: Mutex<Vec<Improvement>>,
+}
+
+impl Incumbent {
+    fn new(plan: Plan, search_start: Instant) -> Self {
+        let cost = plan.exact_cost().unwrap();
+        Incumbent {
+            cost_bits: AtomicU64::new(cost.to_bits()),
+            best: Mutex::new((cost, plan.to_string(), Arc::new(plan))),
+            search_start,
+            improvements: Mutex::new(vec![Improvement {
+                elapsed: Duration::ZERO,
+                cost,
+            }]),
+        }
+    }
+
+    fn bound(&self) -> f64 {
+        f64::from_bits(self.cost_bits.load(Ordering::Acquire))
+    }
+
+    fn offer(&self, cost: f64, plan: Arc<Plan>) {
+        if cost > self.bound() + COST_TOLERANCE {
+            return;
+        }
+
+        let plan_string = plan.to_string();
+        let mut best = self.best.lock().unwrap();
+        if (cost, &plan_string) < (best.0, &best.1) {
+            if cost < best.0 {
+                tracing::info!("Found better plan with cost {:?}", cost);
+                self.improvements.lock().unwrap().push(Improvement {
+                    elapsed: self.search_start.elapsed(),
+                    cost,
+                });
+            }
+            *best = (cost, plan_string, plan);
+            self.cost_bits.store(cost.to_bits(), Ordering::Release);
+        }
+    }
+
+    fn into_result(self) -> (Plan, Vec<Improvement>) {
+        let (_, _, plan) = self.best.into_inner().unwrap();
+        (
+            Arc::try_unwrap(plan).unwrap_or_else(|plan| (*plan).clone()),
+            self.improvements.into_inner().unwrap(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_order::NaturalOrder;
+    use crate::test_util::best;
+
+    #[test]
+    fn same_result_for_any_thread_count() {
+        for (source, target) in [(3, 2), (2, 3), (6, 4), (2, 5), (6, 5), (6, 3), (2, 9)] {
+            let start = State::new(source, target);
+            let expected_cost = best(start).exact_cost();
+
+            let (single, _) =
+                parallel_solver(start, |_| 0.0, &NaturalOrder, CostModel::default(), 1);
+            assert_eq!(single.exact_cost(), expected_cost);
+
+            for threads in [2, 3, 8] {
+                let (multi, _) =
+                    parallel_solver(start, |_| 0.0, &NaturalOrder, CostModel::default(), threads);
+                assert_eq!(multi.to_string(), single.to_string());
+            }
+        }
+    }
+}
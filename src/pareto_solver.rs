@@ -0,0 +1,188 @@
+use crate::action_order::ActionOrder;
+use crate::divider::Divider;
+use crate::naive_solver;
+use crate::plan::{CostModel, Plan, PlanBranch};
+use crate::search_stats::SearchStats;
+use crate::State;
+use std::collections::{HashSet, VecDeque};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// A plan of the frontier found by [`pareto_solver`]
+#[derive(Debug, Clone)]
+pub struct ParetoPoint {
+    pub plan: Plan,
+    pub cost: f64,
+    /// The number of reachable states
+    pub size: usize,
+    /// The number of different numbers of units the plan maps, which are the operations to
+    /// remember to follow it
+    pub maps: usize,
+}
+
+impl ParetoPoint {
+    fn new(plan: Plan, cost: f64) -> Self {
+        let (size, maps) = size_and_maps(&plan);
+        ParetoPoint {
+            plan,
+            cost,
+            size,
+            maps,
+        }
+    }
+
+    /// Whether the point is at least as good in every objective. Costs computed in different ways
+    /// differ by rounding errors
+    fn dominates(&self, cost: f64, size: usize, maps: usize) -> bool {
+        self.cost <= cost + 1e-12 && self.size <= size && self.maps <= maps
+    }
+}
+
+/// Search breadth-first, like [`crate::best_solver::best_solver`], the plans that no other plan
+/// beats in expected throws, size and maps at once. They are returned from the cheapest.
+///
+/// Deciding a pending state never makes the other states unreachable nor removes a map, so the
+/// size and the maps of a partial plan are bounds of its completions, like its cost with an
+/// admissible heuristic. A plan is pruned when a point of the frontier is as good as its bounds.
+pub fn pareto_solver(
+    start: State,
+    mut heuristic: impl FnMut(State) -> f64,
+    order: &impl ActionOrder,
+    cost_model: CostModel,
+) -> (Vec<ParetoPoint>, SearchStats) {
+    let search_start = Instant::now();
+    let divider = Divider::new();
+
+    let mut base_plan = Plan::new(start);
+    base_plan.set_cost_model(cost_model);
+    let mut naive_plan = base_plan.clone();
+    naive_plan.complete_with(naive_solver);
+    let naive_cost = naive_plan.exact_cost().unwrap();
+    let mut frontier = vec![ParetoPoint::new(naive_plan, naive_cost)];
+    let mut stats = SearchStats::default();
+    stats.improved(Duration::ZERO, naive_cost);
+
+    let mut pending = VecDeque::new();
+    let base_plan = Rc::new(base_plan);
+    for (state, action) in base_plan.possible_actions(&divider, order) {
+        pending.push_back((base_plan.clone(), state, action));
+    }
+    let mut visited_plans = HashSet::new();
+
+    while let Some((mut plan, state, action)) = pending.pop_front() {
+        let plan_mut = Rc::make_mut(&mut plan);
+        plan_mut.apply(state, action).unwrap();
+        let cost_start = Instant::now();
+        let cost = plan_mut.cost(&mut heuristic);
+        stats.cost_time += cost_start.elapsed();
+        let (size, maps) = size_and_maps(&plan);
+        // Only maps solve states, so a plan that only throws so far still needs one
+        let maps = maps.max(1);
+
+        stats.generated += 1;
+        if !visited_plans.insert(plan.fingerprint()) {
+            stats.duplicates += 1;
+        } else if cost.value.is_infinite()
+            || frontier
+                .iter()
+                .any(|point| point.dominates(cost.value, size, maps))
+        {
+            stats.pruned += 1;
+        } else if cost.estimated {
+            stats.expanded += 1;
+            for (state, action) in plan.possible_actions(&divider, order) {
+                pending.push_back((plan.clone(), state, action));
+            }
+        } else {
+            tracing::info!(
+                "Found plan with cost {}, {} states and {} maps",
+                cost.value,
+                size,
+                maps
+            );
+            let cheapest = frontier.iter().all(|point| cost.value < point.cost);
+            let point = ParetoPoint::new((*plan).clone(), cost.value);
+            frontier.retain(|other| !point.dominates(other.cost, other.size, other.maps));
+            frontier.push(point);
+            if cheapest {
+                stats.improved(search_start.elapsed(), cost.value);
+            }
+        }
+
+        stats.peak_queue = stats.peak_queue.max(pending.len());
+        stats.peak_visited = stats.peak_visited.max(visited_plans.len());
+        if stats.generated % 100_000 == 0 {
+            tracing::info!(
+                "Iteration {}: queue size is {}, {} plans on the frontier",
+                stats.generated,
+                pending.len(),
+                frontier.len()
+            );
+        }
+    }
+
+    stats.elapsed = search_start.elapsed();
+    stats.expansion_time = stats.elapsed - stats.cost_time;
+    frontier.sort_by(|a, b| a.cost.total_cmp(&b.cost));
+    (frontier, stats)
+}
+
+fn size_and_maps(plan: &Plan) -> (usize, usize) {
+    let reachable = plan.reachable_states();
+    let maps = reachable
+        .iter()
+        .filter_map(|id| match plan.plans()[id] {
+            PlanBranch::Map { units, .. } => Some(units),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    (reachable.len(), maps.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_order::NaturalOrder;
+    use crate::test_util::{assert_close, best};
+
+    #[test]
+    fn frontier() {
+        for (source, target) in [(3, 2), (6, 4), (2, 5), (6, 5), (6, 3), (2, 9)] {
+            let start = State::new(source, target);
+            let (frontier, _) = pareto_solver(start, |_| 0.0, &NaturalOrder, CostModel::default());
+            let best = best(start);
+
+            // The cheapest point is as cheap as the best plan
+            let best_cost = best.exact_cost().unwrap();
+            assert_close(frontier[0].cost, best_cost);
+
+            for (i, point) in frontier.iter().enumerate() {
+                assert_eq!(point.plan.exact_cost(), Some(point.cost));
+                assert_eq!(point.plan.validate(), Ok(()));
+                for (j, other) in frontier.iter().enumerate() {
+                    assert!(i == j || !other.dominates(point.cost, point.size, point.maps));
+                }
+            }
+            // The naive plan is only replaced by plans that are as small
+            let (size, _) = size_and_maps(&naive_solver(start));
+            assert!(frontier.iter().any(|point| point.size <= size));
+        }
+    }
+
+    #[test]
+    fn trade_off() {
+        // The best plan takes more states than a plan that throws more
+        let (frontier, _) = pareto_solver(
+            State::new(2, 9),
+            |_| 0.0,
+            &NaturalOrder,
+            CostModel::default(),
+        );
+        let points = frontier
+            .iter()
+            .map(|point| (point.size, point.maps))
+            .collect::<Vec<_>>();
+        assert_eq!(points, [(10, 1), (7, 1)]);
+        assert_close(frontier[1].cost, 16.0 / 3.0);
+    }
+}
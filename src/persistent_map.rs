@@ -0,0 +1,328 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::Index;
+use std::sync::Arc;
+use std::{fmt, mem};
+
+/// An ordered map whose clones share their structure.
+///
+/// It is a treap: a binary search tree on the keys that is also a heap on a priority derived from
+/// the hash of each key. So the shape of the tree only depends on the keys it holds, and its depth
+/// is logarithmic in the number of keys with high probability.
+///
+/// Cloning the map is O(1). Modifying it copies only the nodes on the path to the modified key
+/// that are still shared with other clones, so it costs O(log n).
+pub struct PersistentMap<K, V> {
+    root: Link<K, V>,
+    len: usize,
+}
+
+type Link<K, V> = Option<Arc<Node<K, V>>>;
+
+#[derive(Clone)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    priority: u64,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+    len: usize,
+}
+
+impl<K: Ord, V> PersistentMap<K, V> {
+    pub fn new() -> Self {
+        PersistentMap { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let mut link = &self.root;
+        while let Some(node) = link {
+            link = match key.cmp(&node.key) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => &node.right,
+                Ordering::Equal => return Some(&node.value),
+            };
+        }
+        None
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Iterate in increasing order of keys
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            len: self.len,
+        };
+        iter.push_left(&self.root);
+        iter
+    }
+}
+
+impl<K: Ord + Hash + Clone, V: Clone> PersistentMap<K, V> {
+    /// Insert the value, returning the previous one
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if self.contains_key(&key) {
+            return Some(replace(&mut self.root, &key, value));
+        }
+
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let node = Node {
+            key,
+            value,
+            priority: hasher.finish(),
+            left: None,
+            right: None,
+        };
+
+        insert_new(&mut self.root, node);
+        self.len += 1;
+        None
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        if !self.contains_key(key) {
+            return None;
+        }
+
+        self.len -= 1;
+        Some(remove(&mut self.root, key))
+    }
+}
+
+/// Replace the value of a key known to be in the tree
+fn replace<K: Ord + Clone, V: Clone>(link: &mut Link<K, V>, key: &K, value: V) -> V {
+    let node = Arc::make_mut(link.as_mut().unwrap());
+    match key.cmp(&node.key) {
+        Ordering::Less => replace(&mut node.left, key, value),
+        Ordering::Greater => replace(&mut node.right, key, value),
+        Ordering::Equal => mem::replace(&mut node.value, value),
+    }
+}
+
+/// Insert a node whose key is not in the tree
+fn insert_new<K: Ord + Clone, V: Clone>(link: &mut Link<K, V>, mut new: Node<K, V>) {
+    match link {
+        Some(node) if node.above(&new) => {
+            let node = Arc::make_mut(node);
+            if new.key < node.key {
+                insert_new(&mut node.left, new);
+            } else {
+                insert_new(&mut node.right, new);
+            }
+        }
+        _ => {
+            let (left, right) = split(link.take(), &new.key);
+            new.left = left;
+            new.right = right;
+            *link = Some(Arc::new(new));
+        }
+    }
+}
+
+/// Remove a key known to be in the tree
+fn remove<K: Ord + Clone, V: Clone>(link: &mut Link<K, V>, key: &K) -> V {
+    let node = link.as_mut().unwrap();
+    match key.cmp(&node.key) {
+        Ordering::Less => remove(&mut Arc::make_mut(node).left, key),
+        Ordering::Greater => remove(&mut Arc::make_mut(node).right, key),
+        Ordering::Equal => {
+            let node = Arc::try_unwrap(link.take().unwrap()).unwrap_or_else(|node| (*node).clone());
+            *link = merge(node.left, node.right);
+            node.value
+        }
+    }
+}
+
+/// Split the tree in the keys smaller and the keys greater than a key that is not in the tree
+fn split<K: Ord + Clone, V: Clone>(link: Link<K, V>, key: &K) -> (Link<K, V>, Link<K, V>) {
+    match link {
+        None => (None, None),
+        Some(mut node) => {
+            let inner = Arc::make_mut(&mut node);
+            if inner.key < *key {
+                let (left, right) = split(inner.right.take(), key);
+                inner.right = left;
+                (Some(node), right)
+            } else {
+                let (left, right) = split(inner.left.take(), key);
+                inner.left = right;
+                (left, Some(node))
+            }
+        }
+    }
+}
+
+/// Merge two trees, where all keys of `left` are smaller than the keys of `right`
+fn merge<K: Ord + Clone, V: Clone>(left: Link<K, V>, right: Link<K, V>) -> Link<K, V> {
+    match (left, right) {
+        (None, link) | (link, None) => link,
+        (Some(mut left), Some(mut right)) => {
+            if left.above(&right) {
+                let inner = Arc::make_mut(&mut left);
+                inner.right = merge(inner.right.take(), Some(right));
+                Some(left)
+            } else {
+                let inner = Arc::make_mut(&mut right);
+                inner.left = merge(Some(left), inner.left.take());
+                Some(right)
+            }
+        }
+    }
+}
+
+impl<K: Ord, V> Node<K, V> {
+    /// Whether this node must be an ancestor of the other one. Ties in priority are broken by the
+    /// keys, so that the shape of the tree is unique
+    fn above(&self, other: &Self) -> bool {
+        (self.priority, &self.key) > (other.priority, &other.key)
+    }
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_left(&mut self, mut link: &'a Link<K, V>) {
+        while let Some(node) = link {
+            self.stack.push(node);
+            link = &node.left;
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left(&node.right);
+        self.len -= 1;
+        Some((&node.key, &node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a PersistentMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Ord + Hash + Clone, V: Clone> FromIterator<(K, V)> for PersistentMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut map = PersistentMap::new();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+impl<K: Ord, V> Index<&K> for PersistentMap<K, V> {
+    type Output = V;
+
+    fn index(&self, key: &K) -> &V {
+        self.get(key).expect("key not found in map")
+    }
+}
+
+impl<K, V> Clone for PersistentMap<K, V> {
+    fn clone(&self) -> Self {
+        PersistentMap {
+            root: self.root.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<K: Ord, V> Default for PersistentMap<K, V> {
+    fn default() -> Self {
+        PersistentMap::new()
+    }
+}
+
+impl<K: Ord + Debug, V: Debug> Debug for PersistentMap<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn same_as_btree_map() {
+        let mut map = PersistentMap::new();
+        let mut expected = BTreeMap::new();
+        let mut snapshots = Vec::new();
+
+        let mut random = 17u64;
+        for i in 0..2000 {
+            random = random
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let key = (random >> 33) % 200;
+
+            if random % 3 == 0 {
+                assert_eq!(map.remove(&key), expected.remove(&key));
+            } else {
+                assert_eq!(map.insert(key, i), expected.insert(key, i));
+            }
+            assert_eq!(map.len(), expected.len());
+
+            if i % 100 == 0 {
+                snapshots.push((map.clone(), expected.clone()));
+            }
+        }
+
+        // Modifying a map never changes its clones
+        for (map, expected) in snapshots {
+            assert!(map.iter().eq(expected.iter()));
+            for key in 0..200 {
+                assert_eq!(map.get(&key), expected.get(&key));
+            }
+        }
+    }
+
+    #[test]
+    fn shape_only_depends_on_keys() {
+        let a = (0..100).map(|i| (i, ())).collect::<PersistentMap<_, _>>();
+        let b = (0..100)
+            .rev()
+            .map(|i| (i, ()))
+            .collect::<PersistentMap<_, _>>();
+
+        assert_eq!(depth(&a.root), depth(&b.root));
+        assert!(depth(&a.root) < 30);
+    }
+
+    fn depth<K, V>(link: &Link<K, V>) -> usize {
+        match link {
+            None => 0,
+            Some(node) => 1 + depth(&node.left).max(depth(&node.right)),
+        }
+    }
+}
@@ -1,15 +1,54 @@
+mod breakdown;
+mod complete;
+mod compose;
 mod cost;
+mod cost_model;
+mod distribution;
+mod fingerprint;
+mod interner;
+mod library;
+mod linear_expression;
+mod objective;
+mod sensitivity;
+mod undo_log;
+mod validate;
 
+use crate::action_order::ActionOrder;
 use crate::divider::Divider;
+use crate::persistent_map::PersistentMap;
 use crate::{Action, State};
-use std::collections::hash_map::Entry;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{HashSet, VecDeque};
 use std::fmt::{Display, Formatter};
+use std::sync::Arc;
 
+pub use breakdown::{CostBreakdown, StateCost};
+pub use compose::Sequence;
+pub use cost::PlanCost;
+pub use cost_model::CostModel;
+pub use distribution::ThrowDistribution;
+pub use fingerprint::PlanFingerprint;
+pub use interner::{StateId, StateInterner};
+pub use library::{LibraryEntry, PlanLibrary};
+pub use linear_expression::LinearExpression;
+pub use objective::Objective;
+pub use sensitivity::{CostFunction, Sensitivity};
+pub use undo_log::UndoLog;
+pub use validate::PlanProblem;
+
+/// A plan is cheap to clone: clones share the branches they have in common, and the interner of
+/// their states
 #[derive(Debug, Clone)]
 pub struct Plan {
-    start: State,
-    plans: HashMap<State, PlanBranch>,
+    interner: Arc<StateInterner>,
+    start: StateId,
+    /// A map rather than a `Vec` indexed by id, so that cloning a plan does not copy its branches
+    plans: PersistentMap<StateId, PlanBranch>,
+    /// The exact costs of the start and of the sub-problems already evaluated, see [`Plan::cost()`]
+    exact_costs: PersistentMap<StateId, f64>,
+    cost_model: CostModel,
+    /// Whether changing a branch removes the states it leaves unreachable
+    auto_prune: bool,
+    library: Option<Arc<PlanLibrary>>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -18,78 +57,206 @@ pub enum PlanBranch {
     Pending {
         min_map_units: u32,
     },
+    /// Follow the plan of the library for this state
+    Library,
     Throw {
-        next: State,
+        next: StateId,
     },
     Map {
         units: u32,
-        sub_problem: State,
-        remaining: Option<State>,
+        sub_problem: StateId,
+        remaining: Option<StateId>,
     },
 }
 
+impl PlanBranch {
+    /// The states this branch leads to
+    pub fn next_states(&self) -> impl Iterator<Item = StateId> {
+        let (first, second) = match *self {
+            PlanBranch::Solved | PlanBranch::Pending { .. } | PlanBranch::Library => (None, None),
+            PlanBranch::Throw { next } => (Some(next), None),
+            PlanBranch::Map {
+                sub_problem,
+                remaining,
+                ..
+            } => (Some(sub_problem), remaining),
+        };
+        first.into_iter().chain(second)
+    }
+
+    /// The states this branch leads to, with the fewest units each of them may map
+    fn next_min_map_units(&self) -> impl Iterator<Item = (StateId, u32)> {
+        let (first, second) = match *self {
+            PlanBranch::Solved | PlanBranch::Pending { .. } | PlanBranch::Library => (None, None),
+            PlanBranch::Throw { next } => (Some((next, 2)), None),
+            PlanBranch::Map {
+                units,
+                sub_problem,
+                remaining,
+            } => (
+                Some((sub_problem, 2)),
+                remaining.map(|remaining| (remaining, units)),
+            ),
+        };
+        first.into_iter().chain(second)
+    }
+}
+
+/// What is needed to revert an edit made with [`Plan::apply_undoable()`] or [`Plan::reset()`]
+#[derive(Debug, Clone)]
+pub struct Undo {
+    /// The branches before the edit, in the order they were changed. `None` for created states
+    previous: Vec<(StateId, Option<PlanBranch>)>,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum ApplyError {
     StateDoesNotExist,
     StateNotPending,
     MapDoesNotDivide,
     MapToFewUnits,
+    TooManyUnits,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ResetError {
+    StateDoesNotExist,
+    StateSolved,
 }
 
 impl Plan {
     pub fn new(start: State) -> Self {
+        Self::with_interner(Arc::new(StateInterner::new()), start)
+    }
+
+    /// Create a plan whose states are interned with other plans of the same problem
+    pub fn with_interner(interner: Arc<StateInterner>, start: State) -> Self {
         let mut plan = Plan {
-            start,
-            plans: HashMap::default(),
+            start: interner.intern(start),
+            interner,
+            plans: PersistentMap::new(),
+            exact_costs: PersistentMap::new(),
+            cost_model: CostModel::default(),
+            auto_prune: true,
+            library: None,
         };
-        plan.ensure_state(start, 2);
+        plan.ensure_state(start, 2, &mut Vec::new());
         plan
     }
 
-    pub fn possible_actions(&self, divider: &Divider) -> Vec<(State, Action)> {
+    /// Build a plan directly from its branches, as previously returned by [`Plan::plans()`]. The
+    /// branches are kept as given, even those that cannot be reached
+    pub fn from_branches(
+        interner: Arc<StateInterner>,
+        start: StateId,
+        branches: impl IntoIterator<Item = (StateId, PlanBranch)>,
+    ) -> Self {
+        Plan {
+            interner,
+            start,
+            plans: branches.into_iter().collect(),
+            exact_costs: PersistentMap::new(),
+            cost_model: CostModel::default(),
+            auto_prune: true,
+            library: None,
+        }
+    }
+
+    /// List the actions that can be applied to the pending states, in increasing order of ids.
+    /// The actions of each state are in the given order
+    pub fn possible_actions(
+        &self,
+        divider: &Divider,
+        order: &impl ActionOrder,
+    ) -> Vec<(StateId, Action)> {
         let mut actions = Vec::new();
 
-        for (&state, branch) in &self.plans {
-            if let &PlanBranch::Pending { min_map_units } = branch {
-                actions.push((state, Action::Throw));
+        for (id, min_map_units) in self.pending_states() {
+            let mut state_actions = self.pending_actions(id, min_map_units, divider);
+            order.order(self, id, &mut state_actions);
+            actions.extend(state_actions.into_iter().map(|action| (id, action)));
+        }
 
-                for &units in divider.divisors(state.target) {
-                    if units > state.units {
-                        break;
-                    }
+        actions
+    }
 
-                    if units >= min_map_units {
-                        actions.push((state, Action::Map(units)));
-                    }
-                }
+    /// The first pending state, in the same order as [`Plan::possible_actions()`], with its actions
+    pub fn first_pending_actions(
+        &self,
+        divider: &Divider,
+        order: &impl ActionOrder,
+    ) -> Option<(StateId, Vec<Action>)> {
+        self.pending_states().next().map(|(id, min_map_units)| {
+            let mut actions = self.pending_actions(id, min_map_units, divider);
+            order.order(self, id, &mut actions);
+            (id, actions)
+        })
+    }
+
+    fn pending_states(&self) -> impl Iterator<Item = (StateId, u32)> + '_ {
+        self.plans.iter().filter_map(|(&id, branch)| match *branch {
+            PlanBranch::Pending { min_map_units } => Some((id, min_map_units)),
+            _ => None,
+        })
+    }
+
+    fn pending_actions(&self, id: StateId, min_map_units: u32, divider: &Divider) -> Vec<Action> {
+        let state = self.state(id);
+        let mut actions = Vec::new();
+        if state.units.checked_mul(state.source).is_some() {
+            actions.push(Action::Throw);
+        }
+
+        for &units in divider.divisors(state.target).iter() {
+            if units > state.units {
+                break;
+            }
+
+            if units >= min_map_units {
+                actions.push(Action::Map(units));
             }
         }
 
         actions
     }
 
-    pub fn apply(&mut self, state: State, action: Action) -> Result<(), ApplyError> {
-        match self.plans.get(&state) {
+    pub fn apply(&mut self, id: StateId, action: Action) -> Result<(), ApplyError> {
+        self.apply_undoable(id, action).map(|_| ())
+    }
+
+    /// Apply the action and return how to revert it with [`Plan::undo()`]
+    pub fn apply_undoable(&mut self, id: StateId, action: Action) -> Result<Undo, ApplyError> {
+        let mut created = Vec::new();
+
+        match self.plans.get(&id) {
             None => Err(ApplyError::StateDoesNotExist),
-            Some(prev_entry) => {
-                let min_map_units = match prev_entry {
-                    &PlanBranch::Pending { min_map_units } => min_map_units,
+            Some(&previous) => {
+                let state = self.state(id);
+                let min_map_units = match previous {
+                    PlanBranch::Pending { min_map_units } => min_map_units,
                     _ => {
                         return Err(ApplyError::StateNotPending);
                     }
                 };
 
                 let branch = match action {
-                    Action::Throw => PlanBranch::Throw {
-                        next: self.ensure_state(
-                            State {
-                                source: state.source,
-                                target: state.target,
-                                units: state.units * state.source,
-                            },
-                            2,
-                        ),
-                    },
+                    Action::Throw => {
+                        let units = state
+                            .units
+                            .checked_mul(state.source)
+                            .ok_or(ApplyError::TooManyUnits)?;
+                        PlanBranch::Throw {
+                            next: self.ensure_state(
+                                State {
+                                    source: state.source,
+                                    target: state.target,
+                                    units,
+                                },
+                                2,
+                                &mut created,
+                            ),
+                        }
+                    }
                     Action::Map(units) => {
                         if state.target % units != 0 {
                             return Err(ApplyError::MapDoesNotDivide);
@@ -104,6 +271,7 @@ impl Plan {
                                 units: 1,
                             },
                             2,
+                            &mut created,
                         );
 
                         let remaining_units = state.units - units;
@@ -115,6 +283,7 @@ impl Plan {
                                     units: remaining_units,
                                 },
                                 units,
+                                &mut created,
                             )
                         });
 
@@ -126,38 +295,195 @@ impl Plan {
                     }
                 };
 
-                self.plans.insert(state, branch);
-                Ok(())
+                self.set_branch(id, branch);
+                let mut previous = vec![(id, Some(previous))];
+                previous.extend(created.into_iter().map(|id| (id, None)));
+                Ok(Undo { previous })
             }
         }
     }
 
-    pub fn start(&self) -> State {
+    /// Make a decided state pending again, and remove the states that can no longer be reached.
+    /// Returns how to revert it with [`Plan::undo()`]
+    pub fn reset(&mut self, id: StateId) -> Result<Undo, ResetError> {
+        let mut previous = Vec::new();
+
+        match self.plans.get(&id) {
+            None => return Err(ResetError::StateDoesNotExist),
+            Some(PlanBranch::Solved) => return Err(ResetError::StateSolved),
+            Some(PlanBranch::Pending { .. }) => return Ok(Undo { previous }),
+            Some(&branch) => previous.push((id, Some(branch))),
+        }
+
+        let decided = self
+            .plans
+            .insert(id, PlanBranch::Pending { min_map_units: 2 });
+        self.forget_branch(id, decided);
+        let removed = self.prune_unreachable();
+        previous.extend(removed.into_iter().map(|(id, branch)| (id, Some(branch))));
+
+        // The remaining states that lead to this one decide how few units it may map
+        let min_map_units = self
+            .plans
+            .iter()
+            .flat_map(|(_, branch)| branch.next_min_map_units())
+            .filter(|&(next, _)| next == id)
+            .map(|(_, units)| units)
+            .chain((id == self.start).then_some(2))
+            .min()
+            .unwrap_or(2);
+        self.set_branch(id, PlanBranch::Pending { min_map_units });
+
+        Ok(Undo { previous })
+    }
+
+    /// Revert an edit. The undos must be applied in the reverse order of the edits
+    pub fn undo(&mut self, undo: Undo) {
+        for (id, branch) in undo.previous.into_iter().rev() {
+            match branch {
+                Some(branch) => self.set_branch(id, branch),
+                None => {
+                    let removed = self.plans.remove(&id);
+                    self.forget_branch(id, removed);
+                }
+            }
+        }
+    }
+
+    pub fn start(&self) -> StateId {
         self.start
     }
 
-    pub fn plans(&self) -> &HashMap<State, PlanBranch> {
+    pub fn interner(&self) -> &Arc<StateInterner> {
+        &self.interner
+    }
+
+    pub fn state(&self, id: StateId) -> State {
+        self.interner.state(id)
+    }
+
+    /// The branches of the plan, in increasing order of ids
+    pub fn plans(&self) -> &PersistentMap<StateId, PlanBranch> {
         &self.plans
     }
 
-    fn ensure_state(&mut self, state: State, min_map_units: u32) -> State {
-        if let Entry::Vacant(vacant) = self.plans.entry(state) {
-            vacant.insert(if state.solved() {
+    /// The states that can be reached from the start, in breadth-first order. States that are not
+    /// in the plan are listed when reached but not followed
+    pub fn reachable_states(&self) -> Vec<StateId> {
+        let mut reachable = vec![self.start];
+        let mut visited = HashSet::from([self.start]);
+
+        let mut i = 0;
+        while let Some(&state) = reachable.get(i) {
+            let branch = self.plans.get(&state);
+            for next in branch.into_iter().flat_map(PlanBranch::next_states) {
+                if visited.insert(next) {
+                    reachable.push(next);
+                }
+            }
+            i += 1;
+        }
+
+        reachable
+    }
+
+    /// Remove the states that cannot be reached from the start, and return their branches.
+    ///
+    /// This gives the smallest plan with the same outputs and cost: there is nothing left to merge.
+    /// States are interned by value, so a sub-problem reached through different paths is already a
+    /// single state. And two different states never behave the same: the probability of each branch
+    /// of a map depends on the units of its state, throws only lead to more units until a map or a
+    /// pending state, and pending states are told apart by their units.
+    pub fn prune_unreachable(&mut self) -> Vec<(StateId, PlanBranch)> {
+        let reachable = self.reachable_states().into_iter().collect::<HashSet<_>>();
+        let unreachable = self
+            .plans
+            .iter()
+            .filter(|(id, _)| !reachable.contains(id))
+            .map(|(&id, &branch)| (id, branch))
+            .collect::<Vec<_>>();
+
+        for &(id, branch) in &unreachable {
+            self.plans.remove(&id);
+            self.forget_branch(id, Some(branch));
+        }
+        unreachable
+    }
+
+    /// Choose whether changing a branch removes the states it leaves unreachable, which is the
+    /// default. Edits reverted exactly with [`Plan::undo()`] never leave unreachable states, so
+    /// turning it off saves a traversal of the plan per undo.
+    pub fn set_auto_prune(&mut self, auto_prune: bool) {
+        self.auto_prune = auto_prune;
+        if auto_prune {
+            self.prune_unreachable();
+        }
+    }
+
+    pub fn cost_model(&self) -> CostModel {
+        self.cost_model
+    }
+
+    /// Evaluate the costs of the plan with this model from now on. The references to a library
+    /// of another model are inlined, as the costs of its plans no longer apply
+    pub fn set_cost_model(&mut self, cost_model: CostModel) {
+        if let Some(library) = &self.library {
+            if library.cost_model() != cost_model {
+                self.inline_library();
+            }
+        }
+        if cost_model != self.cost_model {
+            self.cost_model = cost_model;
+            self.exact_costs = PersistentMap::new();
+        }
+    }
+
+    /// Change a branch, forgetting the exact costs that depend on it if it was decided
+    fn set_branch(&mut self, id: StateId, branch: PlanBranch) {
+        let previous = self.plans.insert(id, branch);
+        self.forget_branch(id, previous);
+
+        // Only a branch that led somewhere can leave states unreachable
+        let had_next = previous.map_or(false, |previous| previous.next_states().next().is_some());
+        if self.auto_prune && had_next {
+            self.prune_unreachable();
+        }
+    }
+
+    /// Deciding a pending state never changes the exact costs, so only other branches matter
+    fn forget_branch(&mut self, id: StateId, previous: Option<PlanBranch>) {
+        if !matches!(previous, None | Some(PlanBranch::Pending { .. })) {
+            self.invalidate_exact_costs(id);
+        }
+    }
+
+    fn ensure_state(
+        &mut self,
+        state: State,
+        min_map_units: u32,
+        created: &mut Vec<StateId>,
+    ) -> StateId {
+        let id = self.interner.intern(state);
+        if !self.plans.contains_key(&id) {
+            let branch = if state.solved() {
                 PlanBranch::Solved
             } else {
                 PlanBranch::Pending { min_map_units }
-            });
+            };
+            self.plans.insert(id, branch);
+            created.push(id);
         }
-        state
+        id
     }
 }
 
 impl Display for Plan {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let start = self.state(self.start);
         writeln!(
             f,
             "Plan for {}: {}/{}",
-            self.start.source, self.start.units, self.start.target
+            start.source, start.units, start.target
         )?;
 
         let mut displayed = HashSet::new();
@@ -165,17 +491,20 @@ impl Display for Plan {
         let mut to_display = VecDeque::new();
         to_display.push_back(self.start);
 
-        while let Some(state) = to_display.pop_front() {
-            write!(f, "{}/{} -> ", state.units, state.target)?;
-            match self.plans[&state] {
+        while let Some(id) = to_display.pop_front() {
+            write!(f, "{} -> ", self.state(id))?;
+            match self.plans[&id] {
                 PlanBranch::Solved => {
                     writeln!(f, "solved")?;
                 }
                 PlanBranch::Pending { min_map_units } => {
                     writeln!(f, "pending (min_map_units = {})", min_map_units)?;
                 }
+                PlanBranch::Library => {
+                    writeln!(f, "from the library")?;
+                }
                 PlanBranch::Throw { next } => {
-                    writeln!(f, "throw to {}/{}", next.units, next.target)?;
+                    writeln!(f, "throw to {}", self.state(next))?;
                     if displayed.insert(next) {
                         to_display.push_back(next);
                     }
@@ -185,16 +514,12 @@ impl Display for Plan {
                     sub_problem,
                     remaining,
                 } => {
-                    write!(
-                        f,
-                        "map {} to {}/{}",
-                        units, sub_problem.units, sub_problem.target
-                    )?;
+                    write!(f, "map {} to {}", units, self.state(sub_problem))?;
                     if displayed.insert(sub_problem) {
                         to_display.push_back(sub_problem);
                     }
                     if let Some(remaining) = remaining {
-                        write!(f, " and {}/{}", remaining.units, remaining.target)?;
+                        write!(f, " and {}", self.state(remaining))?;
                         if displayed.insert(remaining) {
                             to_display.push_back(remaining);
                         }
@@ -204,6 +529,150 @@ impl Display for Plan {
             }
         }
 
+        // The library plans stand on their own, so they do not refer to the library again
+        for id in self.library_references() {
+            if let Some(entry) = self
+                .library
+                .as_ref()
+                .and_then(|library| library.get(self.state(id)))
+            {
+                write!(f, "\n{}", entry.plan)?;
+            }
+        }
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_order::NaturalOrder;
+    use crate::naive_solver;
+    use crate::pareto_solver::pareto_solver;
+    use crate::test_util::best;
+    use std::collections::HashMap;
+
+    /// The number of classes of reachable states that behave the same, by partition refinement:
+    /// states are told apart by their target and their branch, then by the classes it leads to
+    fn equivalence_classes(plan: &Plan) -> usize {
+        let ids = plan.reachable_states();
+        let mut classes = ids.iter().map(|&id| (id, 0)).collect::<HashMap<_, _>>();
+        let mut count = 1;
+        loop {
+            let mut numbers = HashMap::new();
+            let mut refined = HashMap::new();
+            for &id in &ids {
+                let state = plan.state(id);
+                let class = |id| classes[&id];
+                let branch = match plan.plans[&id] {
+                    PlanBranch::Solved => (0, 0, vec![]),
+                    PlanBranch::Pending { .. } => (1, state.units as u64, vec![]),
+                    PlanBranch::Library => unreachable!("The library is inlined"),
+                    PlanBranch::Throw { next } => (2, 0, vec![class(next)]),
+                    PlanBranch::Map {
+                        units,
+                        sub_problem,
+                        remaining,
+                    } => {
+                        let ratio = units as f64 / state.units as f64;
+                        let next = [sub_problem].into_iter().chain(remaining);
+                        (3, ratio.to_bits(), next.map(class).collect())
+                    }
+                };
+                let signature = (class(id), state.target, branch);
+                let number = numbers.len();
+                refined.insert(id, *numbers.entry(signature).or_insert(number));
+            }
+
+            if numbers.len() == count {
+                return count;
+            }
+            count = numbers.len();
+            classes = refined;
+        }
+    }
+
+    #[test]
+    fn no_equivalent_states() {
+        for (source, target) in [(3, 2), (2, 3), (6, 4), (2, 5), (6, 5), (6, 3), (2, 9)] {
+            let start = State::new(source, target);
+            let (frontier, _) = pareto_solver(start, |_| 0.0, &NaturalOrder, CostModel::default());
+            let plans = frontier
+                .into_iter()
+                .map(|point| point.plan)
+                .chain([best(start), naive_solver(start)]);
+            for plan in plans {
+                let states = plan.reachable_states().len();
+                assert_eq!(equivalence_classes(&plan), states, "{}", plan);
+            }
+        }
+    }
+
+    #[test]
+    fn throw_overflow() {
+        let mut plan = Plan::new(State::new(1 << 16, 3));
+        plan.apply(plan.start(), Action::Throw).unwrap();
+        let most = plan.interner.intern(State {
+            source: 1 << 16,
+            target: 3,
+            units: 1 << 16,
+        });
+        assert!(!plan
+            .possible_actions(&Divider::new(), &NaturalOrder)
+            .iter()
+            .any(|&(id, action)| id == most && action == Action::Throw));
+        assert!(matches!(
+            plan.apply(most, Action::Throw),
+            Err(ApplyError::TooManyUnits)
+        ));
+    }
+
+    #[test]
+    fn prune_unreachable() {
+        let start = State::new(6, 4);
+        let mut plan = Plan::new(start);
+        plan.apply(plan.start(), Action::Throw).unwrap();
+        let six = plan.interner.intern(State {
+            source: 6,
+            target: 4,
+            units: 6,
+        });
+        plan.apply(six, Action::Map(4)).unwrap();
+        assert_eq!(plan.plans().len(), 4);
+
+        // States left behind by a plan built from its branches are kept until pruned
+        let three = plan.interner.intern(State {
+            source: 6,
+            target: 4,
+            units: 3,
+        });
+        let branches = plan
+            .plans()
+            .iter()
+            .map(|(&id, &branch)| (id, branch))
+            .chain([(three, PlanBranch::Pending { min_map_units: 2 })]);
+        let mut with_unreachable = Plan::from_branches(plan.interner.clone(), plan.start, branches);
+        assert_eq!(with_unreachable.plans().len(), 5);
+        assert_eq!(with_unreachable, plan);
+
+        let removed = with_unreachable.prune_unreachable();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].0, three);
+        assert_eq!(with_unreachable.plans().len(), 4);
+        assert!(with_unreachable.prune_unreachable().is_empty());
+
+        // Replacing a branch removes the states only it led to, unless turned off
+        let mut pruned = plan.clone();
+        pruned.set_branch(six, PlanBranch::Pending { min_map_units: 2 });
+        assert_eq!(pruned.plans().len(), 2);
+
+        let mut kept = plan.clone();
+        kept.set_auto_prune(false);
+        kept.set_branch(six, PlanBranch::Pending { min_map_units: 2 });
+        assert_eq!(kept.plans().len(), 4);
+        assert_eq!(kept, pruned);
+        kept.set_auto_prune(true);
+        assert_eq!(kept.plans().len(), 2);
+    }
+}
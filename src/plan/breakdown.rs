@@ -0,0 +1,154 @@
+use crate::plan::Plan;
+use crate::State;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// Where the cost of a plan comes from, for each reachable state in breadth-first order
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostBreakdown {
+    pub states: Vec<StateCost>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StateCost {
+    pub state: State,
+    /// The probability to reach the state at least once
+    pub probability: f64,
+    /// The expected number of times the state is reached, more than the probability when the
+    /// state is in a loop
+    pub visits: f64,
+    /// The expected cost from the state on
+    pub remaining_cost: f64,
+    /// The part of the cost of the plan spent in this state: its throw or its map, or the
+    /// estimated cost of a pending state, or the cost of a library plan
+    pub share: f64,
+}
+
+impl Plan {
+    /// Break the cost of the plan down by state, estimating the pending states with the heuristic.
+    ///
+    /// The visits of a state are the expected sum of a reward of 1 in that state, which is solved
+    /// like the cost, through the same cycles. That is one evaluation of the plan per state, which
+    /// is fine for plans of up to a few hundred states.
+    pub fn cost_breakdown(&self, mut heuristic: impl FnMut(State) -> f64) -> CostBreakdown {
+        let ids = self.reachable_states();
+        let own_costs = ids
+            .iter()
+            .map(|&id| {
+                let own_cost = self.cost_in(id);
+                (id, own_cost.unwrap_or_else(|| heuristic(self.state(id))))
+            })
+            .collect::<HashMap<_, _>>();
+        let remaining_costs = self.expected_sums(&ids, |id| own_costs[&id]);
+        let total = remaining_costs[0];
+
+        let states = ids
+            .iter()
+            .zip(remaining_costs)
+            .map(|(&id, remaining_cost)| {
+                let once = |other| if other == id { 1.0 } else { 0.0 };
+                // The visits from the state itself count it and its returns to itself
+                let visits = self.expected_sums(&[self.start, id], once);
+                StateCost {
+                    state: self.state(id),
+                    probability: visits[0] / visits[1],
+                    visits: visits[0],
+                    remaining_cost,
+                    share: if total > 0.0 {
+                        visits[0] * own_costs[&id] / total
+                    } else {
+                        0.0
+                    },
+                }
+            })
+            .collect();
+
+        CostBreakdown { states }
+    }
+}
+
+/// A table with one state per line
+impl Display for CostBreakdown {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<16}{:>16}{:>16}{:>16}{:>16}",
+            "state", "probability", "visits", "remaining cost", "share"
+        )?;
+        for cost in &self.states {
+            writeln!(
+                f,
+                "{:<16}{:>16.6}{:>16.6}{:>16.6}{:>15.2}%",
+                cost.state.to_string(),
+                cost.probability,
+                cost.visits,
+                cost.remaining_cost,
+                cost.share * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::naive_solver;
+    use crate::test_util::{assert_close, best};
+
+    #[test]
+    fn loop_back_to_start() {
+        // 1/3 -> throw to 2/3 -> throw to 4/3 -> map 3 to 1/1 and 1/3
+        let plan = naive_solver(State::new(2, 3));
+        let breakdown = plan.cost_breakdown(|_| unreachable!());
+        let costs = breakdown
+            .states
+            .iter()
+            .map(|cost| (cost.state.to_string(), cost))
+            .collect::<HashMap<_, _>>();
+
+        assert_eq!(breakdown.states[0].state, State::new(2, 3));
+        for state in ["1/3", "2/3", "4/3"] {
+            assert_close(costs[state].probability, 1.0);
+            assert_close(costs[state].visits, 4.0 / 3.0);
+        }
+        assert_close(costs["1/3"].remaining_cost, 8.0 / 3.0);
+        assert_close(costs["2/3"].remaining_cost, 5.0 / 3.0);
+        assert_close(costs["4/3"].remaining_cost, 2.0 / 3.0);
+        assert_close(costs["1/3"].share, 0.5);
+        assert_close(costs["2/3"].share, 0.5);
+        assert_close(costs["4/3"].share, 0.0);
+        assert_close(costs["1/1"].probability, 1.0);
+        assert_close(costs["1/1"].remaining_cost, 0.0);
+    }
+
+    #[test]
+    fn consistent_with_cost() {
+        for (source, target) in [(6, 4), (2, 5), (6, 5), (6, 3), (2, 3)] {
+            let plans = [
+                naive_solver(State::new(source, target)),
+                best(State::new(source, target)),
+            ];
+            for plan in plans {
+                let breakdown = plan.cost_breakdown(|_| unreachable!());
+                let cost = plan.exact_cost().unwrap();
+                assert_eq!(breakdown.states[0].remaining_cost, cost);
+                assert_close(breakdown.states.iter().map(|cost| cost.share).sum(), 1.0);
+                for cost in &breakdown.states {
+                    assert!(cost.probability <= 1.0 + 1e-12, "{:?}", cost);
+                    assert!(cost.visits >= cost.probability - 1e-12, "{:?}", cost);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pending_states_are_estimated() {
+        let mut plan = Plan::new(State::new(6, 4));
+        plan.apply(plan.start(), crate::Action::Throw).unwrap();
+        let breakdown = plan.cost_breakdown(|_| 3.0);
+        assert_close(breakdown.states[0].remaining_cost, 4.0);
+        assert_close(breakdown.states[1].share, 0.75);
+        assert_eq!(breakdown.states[0].remaining_cost, plan.cost(|_| 3.0).value);
+    }
+}
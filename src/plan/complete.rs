@@ -0,0 +1,69 @@
+use crate::plan::{Plan, PlanBranch};
+use crate::State;
+
+impl Plan {
+    /// Decide every pending state as the solver decides it when solving the state alone, so that
+    /// the plan becomes complete. Each solved plan is grafted with [`Plan::graft()`].
+    ///
+    /// The solver may map fewer units in a state than [`PlanBranch::Pending`] requires. Those
+    /// requirements only keep the search from building the same plan twice, so the plan is still
+    /// executable, but [`Plan::validate()`] reports them.
+    pub fn complete_with(&mut self, mut solver: impl FnMut(State) -> Plan) {
+        loop {
+            let pending = self.pending_states().next();
+            let Some((id, _)) = pending else { break };
+            let state = self.state(id);
+            let solved = solver(state);
+
+            self.graft(id, &solved).unwrap();
+            assert!(
+                !matches!(self.plans[&id], PlanBranch::Pending { .. }),
+                "The solver left {} pending",
+                state
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_order::NaturalOrder;
+    use crate::divider::Divider;
+    use crate::test_util::best;
+    use crate::{naive_solver, Action};
+
+    #[test]
+    fn complete_with_naive_solver() {
+        for (source, target) in [(6, 4), (2, 5), (6, 8), (2, 12)] {
+            let mut plan = Plan::new(State::new(source, target));
+            plan.complete_with(naive_solver);
+            assert_eq!(plan, naive_solver(State::new(source, target)));
+        }
+    }
+
+    #[test]
+    fn complete_partial_plan() {
+        let divider = Divider::new();
+        let start = State::new(6, 4);
+        let mut plan = Plan::new(start);
+        plan.apply(plan.start(), Action::Throw).unwrap();
+        let (six, actions) = plan.first_pending_actions(&divider, &NaturalOrder).unwrap();
+        plan.apply(six, actions[1]).unwrap();
+        assert_eq!(plan.exact_cost(), None);
+
+        let mut solved = Vec::new();
+        plan.complete_with(|state| {
+            solved.push(state);
+            best(state)
+        });
+        assert!(plan
+            .first_pending_actions(&divider, &NaturalOrder)
+            .is_none());
+        assert_eq!(plan.validate(), Ok(()));
+
+        // The sub problem and the remaining units were both pending
+        assert_eq!(solved.len(), 2);
+        assert!(plan.exact_cost().unwrap() >= best(start).exact_cost().unwrap());
+    }
+}
@@ -0,0 +1,305 @@
+use crate::plan::{CostModel, Plan, PlanBranch, StateId};
+use crate::State;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy)]
+pub enum GraftError {
+    StateDoesNotExist,
+    /// The grafted plan starts from another state
+    DifferentState,
+}
+
+/// A plan that throws a die built by another plan instead of the source
+#[derive(Debug, Clone)]
+pub struct Sequence {
+    /// Turns throws of the source into a die with as many faces as the source of `plan`
+    pub die: Plan,
+    pub plan: Plan,
+}
+
+impl Plan {
+    /// A plan for the product of the targets of two plans of the same source: `a` is followed, with
+    /// each of its targets multiplied by the target of `b`, and `b` where `a` is solved. Its cost is
+    /// the sum of their costs.
+    ///
+    /// `b` must start from a single unit, as `a` is solved with one unit, and `a` must solve its
+    /// problem somewhere for `b` to start. [`Plan::sequence()`] follows `b` with the outputs of
+    /// `a` as its die instead, and [`Plan::graft()`] continues a plan with another from any state.
+    pub fn product(a: &Plan, b: &Plan) -> Plan {
+        let (mut a, mut b) = (a.clone(), b.clone());
+        a.inline_library();
+        b.inline_library();
+
+        let a_start = a.state(a.start);
+        let b_start = b.state(b.start);
+        assert_eq!(a_start.source, b_start.source, "The sources differ");
+        assert_eq!(b_start.units, 1, "The second plan must start from one unit");
+        assert_eq!(a.cost_model, b.cost_model, "The cost models differ");
+
+        let scale = |state: State| State {
+            target: state.target * b_start.target,
+            ..state
+        };
+        let mut product = Plan::new(scale(a_start));
+        product.cost_model = a.cost_model;
+
+        for id in a.reachable_states() {
+            let state = a.state(id);
+            let branch = match a.plans[&id] {
+                // Where `a` is solved, `b` starts
+                PlanBranch::Solved => PlanBranch::Pending { min_map_units: 2 },
+                PlanBranch::Library => unreachable!("The library is inlined"),
+                PlanBranch::Pending { min_map_units } => PlanBranch::Pending { min_map_units },
+                PlanBranch::Throw { next } => PlanBranch::Throw {
+                    next: product.interner.intern(scale(a.state(next))),
+                },
+                PlanBranch::Map {
+                    units,
+                    sub_problem,
+                    remaining,
+                } => PlanBranch::Map {
+                    units,
+                    sub_problem: product.interner.intern(scale(a.state(sub_problem))),
+                    remaining: remaining.map(|next| product.interner.intern(scale(a.state(next)))),
+                },
+            };
+            product.set_branch(product.interner.intern(scale(state)), branch);
+        }
+
+        let b_id = product.interner.get(b_start);
+        let b_id = b_id.expect("The first plan never solves its problem");
+        product.graft(b_id, &b).unwrap();
+        product
+    }
+
+    /// Follow `b` with the outputs of `a` as its die, for the target of `b` from the source of `a`.
+    /// The target of `a` must be the source of `b`, and both plans must have the same cost model.
+    pub fn sequence(a: &Plan, b: &Plan) -> Sequence {
+        let a_start = a.state(a.start);
+        let b_start = b.state(b.start);
+        assert_eq!(a_start.units, 1, "The die must start from one unit");
+        assert_eq!(
+            a_start.target, b_start.source,
+            "The die has another number of faces"
+        );
+        assert_eq!(a.cost_model, b.cost_model, "The cost models differ");
+
+        Sequence {
+            die: a.clone(),
+            plan: b.clone(),
+        }
+    }
+
+    /// Decide a state as `plan` decides its start, which must be the same state, and copy the
+    /// branches of `plan` for the states it leads to. A decided state is reset first, so that it is
+    /// planned again. The other states already decided in this plan keep their branches, which are
+    /// as good a way to go on from them.
+    ///
+    /// The references of `plan` to its library are kept if this plan can share the library, with
+    /// the same cost model, and inlined otherwise.
+    pub fn graft(&mut self, id: StateId, plan: &Plan) -> Result<(), GraftError> {
+        match self.plans.get(&id) {
+            None => return Err(GraftError::StateDoesNotExist),
+            Some(_) if plan.state(plan.start) != self.state(id) => {
+                return Err(GraftError::DifferentState)
+            }
+            // `plan` solves it too
+            Some(PlanBranch::Solved) => {}
+            Some(_) => {
+                self.reset(id).unwrap();
+            }
+        }
+
+        let inlined;
+        let plan = match (&self.library, &plan.library) {
+            (_, None) => plan,
+            (Some(ours), Some(theirs)) if Arc::ptr_eq(ours, theirs) => plan,
+            (None, Some(theirs)) if theirs.cost_model() == self.cost_model => {
+                self.library = Some(theirs.clone());
+                plan
+            }
+            (_, Some(_)) => {
+                let mut plan = plan.clone();
+                plan.inline_library();
+                inlined = plan;
+                &inlined
+            }
+        };
+
+        for grafted_id in plan.reachable_states() {
+            let id = self.interner.intern(plan.state(grafted_id));
+            let grafted = plan.plans[&grafted_id];
+            match self.plans.get(&id) {
+                None => {}
+                Some(PlanBranch::Pending { .. })
+                    if !matches!(grafted, PlanBranch::Pending { .. }) => {}
+                Some(_) => continue,
+            }
+
+            let translate = |next| self.interner.intern(plan.state(next));
+            let branch = match grafted {
+                PlanBranch::Solved | PlanBranch::Pending { .. } | PlanBranch::Library => grafted,
+                PlanBranch::Throw { next } => PlanBranch::Throw {
+                    next: translate(next),
+                },
+                PlanBranch::Map {
+                    units,
+                    sub_problem,
+                    remaining,
+                } => PlanBranch::Map {
+                    units,
+                    sub_problem: translate(sub_problem),
+                    remaining: remaining.map(translate),
+                },
+            };
+            self.set_branch(id, branch);
+        }
+
+        Ok(())
+    }
+}
+
+impl Sequence {
+    /// The expected cost, `None` if a plan is not complete. Each throw of `plan` is a run of `die`
+    /// that does not depend on the others, so by Wald's identity the cost is that of `plan` with
+    /// the expected cost of `die` for the fixed part of each throw.
+    pub fn exact_cost(&self) -> Option<f64> {
+        let die_cost = self.die.exact_cost()?;
+        let mut plan = self.plan.clone();
+        plan.set_cost_model(CostModel {
+            throw: die_cost,
+            ..plan.cost_model
+        });
+        plan.exact_cost()
+    }
+}
+
+/// The plan of the die, then the plan that throws it
+impl Display for Sequence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\n{}", self.die, self.plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{assert_close, best};
+    use crate::{naive_solver, Action};
+
+    #[test]
+    fn product() {
+        for (source, a, b) in [(6, 2, 6), (6, 2, 2), (2, 3, 2), (6, 5, 3), (6, 1, 4)] {
+            for (a, b) in [
+                (best(State::new(source, a)), best(State::new(source, b))),
+                (
+                    naive_solver(State::new(source, a)),
+                    best(State::new(source, b)),
+                ),
+            ] {
+                let product = Plan::product(&a, &b);
+                let start = product.state(product.start());
+                assert_eq!(
+                    start,
+                    State::new(
+                        source,
+                        a.state(a.start()).target * b.state(b.start()).target
+                    )
+                );
+                assert_eq!(product.validate(), Ok(()), "{}", product);
+
+                let expected = a.exact_cost().unwrap() + b.exact_cost().unwrap();
+                let cost = product.exact_cost().unwrap();
+                assert_close(cost, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn sequence() {
+        for (source, faces, target) in [(6, 2, 4), (2, 3, 5), (6, 4, 3)] {
+            let die = best(State::new(source, faces));
+            let plan = best(State::new(faces, target));
+            let die_cost = die.exact_cost().unwrap();
+            let sequence = Plan::sequence(&die, &plan);
+            assert_close(
+                sequence.exact_cost().unwrap(),
+                die_cost * plan.exact_cost().unwrap(),
+            );
+
+            // Each throw of the plan is a run of the die, and its maps cost as before
+            let cost_model = CostModel {
+                throw: 2.0,
+                map: 0.5,
+                unit: 0.25,
+            };
+            let (mut die, mut plan) = (die, plan);
+            die.set_cost_model(cost_model);
+            plan.set_cost_model(cost_model);
+            let plan_cost = plan.cost_function().unwrap();
+            let die_cost = die.exact_cost().unwrap();
+            assert_close(
+                Plan::sequence(&die, &plan).exact_cost().unwrap(),
+                plan_cost.throws * die_cost + plan_cost.maps * 0.5 + plan_cost.units * 0.25,
+            );
+            let mut pending = Plan::new(State::new(faces, target));
+            pending.set_cost_model(cost_model);
+            assert_eq!(Plan::sequence(&die, &pending).exact_cost(), None);
+        }
+    }
+
+    #[test]
+    fn graft() {
+        let start = State::new(6, 4);
+        let mut plan = Plan::new(start);
+        plan.apply(plan.start(), Action::Throw).unwrap();
+        let six = State {
+            source: 6,
+            target: 4,
+            units: 6,
+        };
+        let six_id = plan.interner.get(six).unwrap();
+
+        let mut other = Plan::new(six);
+        other.apply(other.start(), Action::Map(4)).unwrap();
+        assert!(matches!(
+            plan.graft(plan.start(), &other),
+            Err(GraftError::DifferentState)
+        ));
+        assert!(matches!(
+            plan.graft(six_id, &Plan::new(start)),
+            Err(GraftError::DifferentState)
+        ));
+
+        plan.graft(six_id, &other).unwrap();
+        assert_eq!(plan.validate(), Ok(()));
+        let mut expected = Plan::new(start);
+        expected.apply(expected.start(), Action::Throw).unwrap();
+        expected
+            .apply(expected.interner.get(six).unwrap(), Action::Map(4))
+            .unwrap();
+        assert_eq!(plan, expected);
+
+        // Grafting a complete plan into the last pending state completes the plan
+        plan.graft(
+            plan.interner.get(State { units: 2, ..six }).unwrap(),
+            &naive_solver(State { units: 2, ..six }),
+        )
+        .unwrap();
+        let mut naive = expected.clone();
+        naive.complete_with(naive_solver);
+        assert_eq!(plan, naive);
+        assert!(plan.exact_cost().is_some());
+
+        // Grafting into a decided state plans it again, without the states it no longer leads to
+        let mut throw = Plan::new(six);
+        throw.apply(throw.start(), Action::Throw).unwrap();
+        plan.graft(six_id, &throw).unwrap();
+        assert_eq!(plan.validate(), Ok(()));
+        assert_eq!(plan.plans().len(), 3);
+        assert_eq!(plan.exact_cost(), None);
+        plan.graft(six_id, &naive_solver(six)).unwrap();
+        assert_eq!(plan, naive);
+    }
+}
@@ -1,22 +1,13 @@
-use crate::plan::{Plan, PlanBranch};
+use crate::plan::{LinearExpression, Plan, PlanBranch, StateId};
 use crate::State;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-#[derive(Debug, Clone, Copy)]
+/// The cost of the plan from a state, where the variables are the costs of the states of cycles not
+/// solved yet, and of the states the plan does not decide when evaluated symbolically
+#[derive(Debug, Clone)]
 struct InnerPlanCost {
     estimated: bool,
-    value: InnerPlanCostValue,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum InnerPlanCostValue {
-    NonCycle(f64),
-    /// The cycle cost expressed as `a * x + b`, where `cost(base) = x`
-    Cycle {
-        base: State,
-        a: f64,
-        b: f64,
-    },
+    value: LinearExpression<StateId>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -25,40 +16,170 @@ pub struct PlanCost {
     pub value: f64,
 }
 
+struct Evaluation<C> {
+    /// The cost spent in a state, given its known cost if the plan decides it
+    cost_in: C,
+    /// Whether the known costs are spent in the states, so that the exact costs the plan keeps
+    /// apply
+    use_exact_costs: bool,
+    /// The costs of the start and of the sub-problems, each evaluated on its own, so that the
+    /// sub-problems mapped to from many states are evaluated once
+    computed: HashMap<StateId, InnerPlanCost>,
+}
+
 impl Plan {
     pub fn exact_cost(&self) -> Option<f64> {
-        let cost = self.cost(|_| 0.0);
+        let cost = self.clone().cost(|_| 0.0);
 
         (!cost.estimated).then_some(cost.value)
     }
 
-    pub fn cost(&self, mut heuristic: impl FnMut(State) -> f64) -> PlanCost {
-        self.cost_for(self.start, &mut heuristic)
-    }
+    /// Evaluate the cost of the plan, with the heuristic for the states it does not decide.
+    ///
+    /// The exact costs of the start and of the sub-problems, whose sub-plans are complete, are kept
+    /// in the plan, so that the next evaluations only go through the sub-plans still estimated.
+    /// They do not depend on the heuristic, and deciding a pending state never changes them.
+    pub fn cost(&mut self, mut heuristic: impl FnMut(State) -> f64) -> PlanCost {
+        let mut evaluation = Evaluation {
+            cost_in: |id, known: Option<f64>| {
+                LinearExpression::constant(known.unwrap_or_else(|| heuristic(self.state(id))))
+            },
+            use_exact_costs: true,
+            computed: HashMap::new(),
+        };
+        let cost = self.cost_for(self.start, &mut evaluation);
+
+        for (id, computed) in evaluation.computed {
+            if let (false, Some(value)) = (computed.estimated, computed.value.as_constant()) {
+                self.exact_costs.insert(id, value);
+            }
+        }
 
-    fn cost_for(&self, state: State, heuristic: &mut impl FnMut(State) -> f64) -> PlanCost {
-        let cost = self.inner_cost(&mut HashSet::new(), state, heuristic);
+        PlanCost {
+            estimated: cost.estimated,
+            value: cost
+                .value
+                .as_constant()
+                .unwrap_or_else(|| unreachable!("All cycles must have been solved")),
+        }
+    }
 
-        match cost.value {
-            InnerPlanCostValue::NonCycle(value) => PlanCost {
-                estimated: cost.estimated,
-                value,
+    /// The cost of the plan as a function of the costs of the states it does not decide
+    pub fn symbolic_cost(&self) -> LinearExpression<State> {
+        let mut evaluation = Evaluation {
+            cost_in: |id, known: Option<f64>| {
+                known.map_or_else(
+                    || LinearExpression::variable(id),
+                    LinearExpression::constant,
+                )
             },
-            _ => unreachable!("All cycle must have been solved"),
+            use_exact_costs: true,
+            computed: HashMap::new(),
+        };
+        let cost = self.cost_for(self.start, &mut evaluation);
+        cost.value.map_variables(|id| self.state(id))
+    }
+
+    /// The expected sum of `reward` over the states the plan goes through from each of `from`,
+    /// counting a state each time it is reached. The cost of the plan is this sum with the cost
+    /// spent in each state as its reward, and a reward of 1 in a single state gives its expected
+    /// number of visits
+    pub(super) fn expected_sums(
+        &self,
+        from: &[StateId],
+        mut reward: impl FnMut(StateId) -> f64,
+    ) -> Vec<f64> {
+        let mut evaluation = Evaluation {
+            cost_in: |id, _| LinearExpression::constant(reward(id)),
+            use_exact_costs: false,
+            computed: HashMap::new(),
+        };
+        from.iter()
+            .map(|&id| {
+                let sum = self.cost_for(id, &mut evaluation).value;
+                sum.as_constant()
+                    .unwrap_or_else(|| unreachable!("All cycles must have been solved"))
+            })
+            .collect()
+    }
+
+    /// Forget the exact costs that may depend on a decided branch that changed: those of its
+    /// target, and of the targets it is a sub-problem of, which are its multiples
+    pub(super) fn invalidate_exact_costs(&mut self, changed: StateId) {
+        if self.exact_costs.is_empty() {
+            return;
+        }
+
+        let target = self.state(changed).target;
+        let stale = self
+            .exact_costs
+            .iter()
+            .map(|(&id, _)| id)
+            .filter(|&id| self.state(id).target % target == 0)
+            .collect::<Vec<_>>();
+        for id in stale {
+            self.exact_costs.remove(&id);
+        }
+    }
+
+    /// The cost spent in a state: its throw or its map, or the cost of its library plan. `None` if
+    /// the plan does not decide the state
+    pub(super) fn cost_in(&self, id: StateId) -> Option<f64> {
+        let state = self.state(id);
+        match self.plans[&id] {
+            PlanBranch::Solved => Some(0.0),
+            PlanBranch::Pending { .. } => None,
+            PlanBranch::Library => {
+                let library = self.library.as_ref();
+                library
+                    .and_then(|library| library.get(state))
+                    .map(|entry| entry.cost)
+            }
+            PlanBranch::Throw { .. } => Some(self.cost_model.throw_cost(state)),
+            PlanBranch::Map { .. } => Some(self.cost_model.map_cost(state)),
         }
     }
 
+    fn cost_for(
+        &self,
+        state: StateId,
+        evaluation: &mut Evaluation<impl FnMut(StateId, Option<f64>) -> LinearExpression<StateId>>,
+    ) -> InnerPlanCost {
+        if let Some(&value) = self
+            .exact_costs
+            .get(&state)
+            .filter(|_| evaluation.use_exact_costs)
+        {
+            return InnerPlanCost {
+                estimated: false,
+                value: LinearExpression::constant(value),
+            };
+        }
+        if let Some(cost) = evaluation.computed.get(&state) {
+            return cost.clone();
+        }
+
+        let cost = self.inner_cost(&mut HashSet::new(), state, evaluation);
+        evaluation.computed.insert(state, cost.clone());
+        cost
+    }
+
     fn inner_cost(
         &self,
-        visited_branching: &mut HashSet<State>,
-        state: State,
-        heuristic: &mut impl FnMut(State) -> f64,
+        visited_branching: &mut HashSet<StateId>,
+        state: StateId,
+        evaluation: &mut Evaluation<impl FnMut(StateId, Option<f64>) -> LinearExpression<StateId>>,
     ) -> InnerPlanCost {
         match self.plans[&state] {
-            PlanBranch::Solved => InnerPlanCost::exact(0.0),
-            PlanBranch::Pending { .. } => InnerPlanCost::estimated(heuristic(state)),
+            PlanBranch::Solved | PlanBranch::Pending { .. } | PlanBranch::Library => {
+                let known = self.cost_in(state);
+                InnerPlanCost {
+                    estimated: known.is_none(),
+                    value: (evaluation.cost_in)(state, known),
+                }
+            }
             PlanBranch::Throw { next } => {
-                self.inner_throw_cost(visited_branching, state, heuristic, next)
+                self.inner_throw_cost(visited_branching, state, evaluation, next)
             }
             PlanBranch::Map {
                 units,
@@ -67,7 +188,7 @@ impl Plan {
             } => self.inner_map_cost(
                 visited_branching,
                 state,
-                heuristic,
+                evaluation,
                 units,
                 sub_problem,
                 remaining,
@@ -77,17 +198,19 @@ impl Plan {
 
     fn inner_throw_cost(
         &self,
-        visited_branching: &mut HashSet<State>,
-        state: State,
-        heuristic: &mut impl FnMut(State) -> f64,
-        next: State,
+        visited_branching: &mut HashSet<StateId>,
+        state: StateId,
+        evaluation: &mut Evaluation<impl FnMut(StateId, Option<f64>) -> LinearExpression<StateId>>,
+        next: StateId,
     ) -> InnerPlanCost {
         if !visited_branching.insert(state) {
             return InnerPlanCost::cycle_start(state);
         }
 
-        let next_cost = self.inner_cost(visited_branching, next, heuristic);
-        let this_value = next_cost.value.solve_linear_equation(state, 1.0, 1.0);
+        let next_cost = self.inner_cost(visited_branching, next, evaluation);
+        let throw_cost = self.cost_model.throw_cost(self.state(state));
+        let throw_cost = (evaluation.cost_in)(state, Some(throw_cost));
+        let this_value = next_cost.value.add(&throw_cost).solve_equals_to(state);
 
         InnerPlanCost {
             estimated: next_cost.estimated,
@@ -97,32 +220,35 @@ impl Plan {
 
     fn inner_map_cost(
         &self,
-        visited_branching: &mut HashSet<State>,
-        state: State,
-        heuristic: &mut impl FnMut(State) -> f64,
+        visited_branching: &mut HashSet<StateId>,
+        state: StateId,
+        evaluation: &mut Evaluation<impl FnMut(StateId, Option<f64>) -> LinearExpression<StateId>>,
         units: u32,
-        sub_problem: State,
-        remaining: Option<State>,
+        sub_problem: StateId,
+        remaining: Option<StateId>,
     ) -> InnerPlanCost {
         if !visited_branching.insert(state) {
             return InnerPlanCost::cycle_start(state);
         }
 
-        let sub_cost = self.cost_for(sub_problem, heuristic);
+        let sub_cost = self.cost_for(sub_problem, evaluation);
+        let map_cost = self.cost_model.map_cost(self.state(state));
+        let map_cost = (evaluation.cost_in)(state, Some(map_cost));
 
         match remaining {
             None => InnerPlanCost {
                 estimated: sub_cost.estimated,
-                value: InnerPlanCostValue::NonCycle(sub_cost.value),
+                value: sub_cost.value.add(&map_cost),
             },
             Some(remaining) => {
-                let ratio = units as f64 / state.units as f64;
-                let remaining_cost = self.inner_cost(visited_branching, remaining, heuristic);
-                let this_value = remaining_cost.value.solve_linear_equation(
-                    state,
-                    1.0 - ratio,
-                    ratio * sub_cost.value,
-                );
+                let ratio = units as f64 / self.state(state).units as f64;
+                let remaining_cost = self.inner_cost(visited_branching, remaining, evaluation);
+                let this_value = remaining_cost
+                    .value
+                    .mul(1.0 - ratio)
+                    .add(&sub_cost.value.mul(ratio))
+                    .add(&map_cost)
+                    .solve_equals_to(state);
 
                 InnerPlanCost {
                     estimated: sub_cost.estimated || remaining_cost.estimated,
@@ -134,48 +260,10 @@ impl Plan {
 }
 
 impl InnerPlanCost {
-    fn exact(value: f64) -> Self {
+    fn cycle_start(base: StateId) -> Self {
         InnerPlanCost {
             estimated: false,
-            value: InnerPlanCostValue::NonCycle(value),
-        }
-    }
-
-    fn estimated(value: f64) -> Self {
-        InnerPlanCost {
-            estimated: true,
-            value: InnerPlanCostValue::NonCycle(value),
-        }
-    }
-
-    fn cycle_start(base: State) -> Self {
-        InnerPlanCost {
-            estimated: false,
-            value: InnerPlanCostValue::Cycle {
-                base,
-                a: 1.0,
-                b: 0.0,
-            },
-        }
-    }
-}
-
-impl InnerPlanCostValue {
-    /// Solve the equation `x = p * self + q` for `x`, where `self` may or may not depend on `x`
-    fn solve_linear_equation(self, state: State, p: f64, q: f64) -> InnerPlanCostValue {
-        match self {
-            InnerPlanCostValue::NonCycle(z) => InnerPlanCostValue::NonCycle(p * z + q),
-            InnerPlanCostValue::Cycle { base, a, b } => {
-                if base != state {
-                    InnerPlanCostValue::Cycle {
-                        base,
-                        a: a * p,
-                        b: b * p + q,
-                    }
-                } else {
-                    InnerPlanCostValue::NonCycle((b * p + q) / (1.0 - a * p))
-                }
-            }
+            value: LinearExpression::variable(base),
         }
     }
 }
@@ -183,43 +271,15 @@ impl InnerPlanCostValue {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::action_order::NaturalOrder;
+    use crate::divider::Divider;
+    use crate::plan::CostModel;
+    use crate::test_util::assert_close;
     use crate::Action;
 
-    #[test]
-    fn solve_linear_equation() {
-        let state_1 = State::new(1, 1);
-        let state_2 = State::new(2, 2);
-
-        let value = InnerPlanCostValue::NonCycle(3.0).solve_linear_equation(state_1, 4.0, 5.0);
-        assert_eq!(value, InnerPlanCostValue::NonCycle(17.0));
-
-        let value = InnerPlanCostValue::Cycle {
-            base: state_2,
-            a: 3.0,
-            b: 4.0,
-        }
-        .solve_linear_equation(state_1, 5.0, 6.0);
-        assert_eq!(
-            value,
-            InnerPlanCostValue::Cycle {
-                base: state_2,
-                a: 15.0,
-                b: 26.0
-            }
-        );
-
-        let value = InnerPlanCostValue::Cycle {
-            base: state_1,
-            a: 3.0,
-            b: 4.0,
-        }
-        .solve_linear_equation(state_1, 5.0, 6.0);
-        assert_eq!(value, InnerPlanCostValue::NonCycle(-26.0 / 14.0));
-    }
-
     #[test]
     fn cost_solved() {
-        let plan = Plan::new(State::new(5, 1));
+        let mut plan = Plan::new(State::new(5, 1));
         let cost = plan.cost(|_| unreachable!());
         assert_eq!(
             cost,
@@ -232,7 +292,7 @@ mod tests {
 
     #[test]
     fn cost_pending() {
-        let plan = Plan::new(State::new(5, 2));
+        let mut plan = Plan::new(State::new(5, 2));
         let cost = plan.cost(|_| 7.0);
         assert_eq!(
             cost,
@@ -247,7 +307,7 @@ mod tests {
     fn cost_throw() {
         let state = State::new(5, 2);
         let mut plan = Plan::new(state);
-        plan.apply(state, Action::Throw).unwrap();
+        plan.apply(plan.start(), Action::Throw).unwrap();
         let cost = plan.cost(|_| 7.0);
         assert_eq!(
             cost,
@@ -256,13 +316,17 @@ mod tests {
                 value: 8.0
             }
         );
-
-        plan.plans.insert(
-            State {
-                source: 5,
-                target: 2,
-                units: 5,
-            },
+        // Each evaluation uses its own heuristic
+        assert_eq!(plan.cost(|_| 1.0).value, 2.0);
+
+        plan.set_branch(
+            plan.interner
+                .get(State {
+                    source: 5,
+                    target: 2,
+                    units: 5,
+                })
+                .unwrap(),
             PlanBranch::Solved,
         );
         let cost = plan.cost(|_| unreachable!());
@@ -294,7 +358,7 @@ mod tests {
         };
 
         let mut plan = Plan::new(state);
-        plan.apply(state, Action::Map(2)).unwrap();
+        plan.apply(plan.start(), Action::Map(2)).unwrap();
 
         let mut i = 0;
         let cost = plan.cost(|state| {
@@ -318,7 +382,7 @@ mod tests {
             }
         );
 
-        plan.plans.insert(remaining, PlanBranch::Solved);
+        plan.set_branch(plan.interner.get(remaining).unwrap(), PlanBranch::Solved);
         let mut i = 0;
         let cost = plan.cost(|state| {
             if i == 0 {
@@ -337,7 +401,7 @@ mod tests {
             }
         );
 
-        plan.plans.insert(sub_state, PlanBranch::Solved);
+        plan.set_branch(plan.interner.get(sub_state).unwrap(), PlanBranch::Solved);
         let cost = plan.cost(|_| unreachable!());
         assert_eq!(
             cost,
@@ -362,7 +426,7 @@ mod tests {
         };
 
         let mut plan = Plan::new(state);
-        plan.apply(state, Action::Map(2)).unwrap();
+        plan.apply(plan.start(), Action::Map(2)).unwrap();
 
         let mut i = 0;
         let cost = plan.cost(|state| {
@@ -382,7 +446,7 @@ mod tests {
             }
         );
 
-        plan.plans.insert(sub_state, PlanBranch::Solved);
+        plan.set_branch(plan.interner.get(sub_state).unwrap(), PlanBranch::Solved);
         let cost = plan.cost(|_| unreachable!());
         assert_eq!(
             cost,
@@ -397,25 +461,25 @@ mod tests {
     fn cycle() {
         let state = State::new(2, 3);
         let mut plan = Plan::new(state);
-        plan.apply(state, Action::Throw).unwrap();
-        plan.apply(
-            State {
+        plan.apply(plan.start(), Action::Throw).unwrap();
+        let two = plan
+            .interner
+            .get(State {
                 source: 2,
                 target: 3,
                 units: 2,
-            },
-            Action::Throw,
-        )
-        .unwrap();
-        plan.apply(
-            State {
+            })
+            .unwrap();
+        plan.apply(two, Action::Throw).unwrap();
+        let four = plan
+            .interner
+            .get(State {
                 source: 2,
                 target: 3,
                 units: 4,
-            },
-            Action::Map(3),
-        )
-        .unwrap();
+            })
+            .unwrap();
+        plan.apply(four, Action::Map(3)).unwrap();
 
         let cost = plan.cost(|_| unreachable!());
         assert_eq!(
@@ -426,4 +490,126 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn cost_model() {
+        // 1/3 -> throw to 2/3 -> throw to 4/3 -> map 3 to 1/1 and 1/3
+        let mut plan = crate::naive_solver(State::new(2, 3));
+        assert_eq!(plan.cost(|_| unreachable!()).value, 8.0 / 3.0);
+
+        plan.set_cost_model(CostModel {
+            throw: 2.0,
+            map: 1.0,
+            unit: 0.0,
+        });
+        assert_eq!(plan.exact_cost(), Some(20.0 / 3.0));
+        assert_eq!(plan.cost(|_| unreachable!()).value, 20.0 / 3.0);
+
+        let cost_model = CostModel {
+            throw: 2.0,
+            map: 1.0,
+            unit: 0.5,
+        };
+        plan.set_cost_model(cost_model);
+        let cost = plan.cost(|_| unreachable!()).value;
+        assert_close(cost, (2.5 + 3.0 + 3.0) * 4.0 / 3.0);
+        let breakdown = plan.cost_breakdown(|_| unreachable!());
+        assert_close(breakdown.states[0].remaining_cost, cost);
+
+        plan.set_cost_model(CostModel::default());
+        assert_eq!(plan.cost(|_| unreachable!()).value, 8.0 / 3.0);
+    }
+
+    #[test]
+    fn symbolic_cost() {
+        // 1/3 -> throw to 2/3 -> throw to 4/3, which is pending
+        let mut plan = Plan::new(State::new(2, 3));
+        plan.apply(plan.start(), Action::Throw).unwrap();
+        let two = State {
+            source: 2,
+            target: 3,
+            units: 2,
+        };
+        plan.apply(plan.interner.get(two).unwrap(), Action::Throw)
+            .unwrap();
+        assert_eq!(plan.symbolic_cost().to_string(), "2 + [4/3]");
+
+        // Mapping 3 of the 4 units gives back 1/3, which closes a cycle
+        let four = State {
+            source: 2,
+            target: 3,
+            units: 4,
+        };
+        plan.apply(plan.interner.get(four).unwrap(), Action::Map(3))
+            .unwrap();
+        assert_eq!(plan.symbolic_cost().as_constant(), Some(8.0 / 3.0));
+
+        // 1/4 -> throw to 6/4 -> map 2 to 1/2 and 4/4, both pending
+        let mut plan = Plan::new(State::new(6, 4));
+        plan.apply(plan.start(), Action::Throw).unwrap();
+        let six = State {
+            source: 6,
+            target: 4,
+            units: 6,
+        };
+        plan.apply(plan.interner.get(six).unwrap(), Action::Map(2))
+            .unwrap();
+        let cost = plan.symbolic_cost();
+        assert_eq!(
+            cost.to_string(),
+            "1 + 0.3333333333333333 [1/2] + 0.6666666666666667 [4/4]"
+        );
+
+        // Replacing the variables by the heuristic gives the numeric cost
+        let heuristic = |state: State| state.units as f64 + 1.0;
+        let remaining = State {
+            source: 6,
+            target: 4,
+            units: 4,
+        };
+        let value = [State::new(6, 2), remaining]
+            .into_iter()
+            .fold(cost, |cost, state| {
+                cost.substitute(state, &LinearExpression::constant(heuristic(state)))
+            })
+            .as_constant()
+            .unwrap();
+        assert_close(value, plan.cost(heuristic).value);
+    }
+
+    #[test]
+    fn exact_costs() {
+        // The same branches, evaluated without any kept cost
+        let evaluate = |plan: &Plan, heuristic: fn(State) -> f64| {
+            let branches = plan.plans().iter().map(|(&id, &branch)| (id, branch));
+            Plan::from_branches(plan.interner().clone(), plan.start(), branches).cost(heuristic)
+        };
+        let heuristics: [fn(State) -> f64; 2] = [|_| 0.0, |state| state.units as f64];
+
+        let divider = Divider::new();
+        let mut plan = Plan::new(State::new(6, 8));
+        let mut undos = Vec::new();
+        while let Some((id, actions)) = plan.first_pending_actions(&divider, &NaturalOrder) {
+            undos.push(plan.apply_undoable(id, actions[actions.len() / 2]).unwrap());
+            for heuristic in heuristics {
+                assert_eq!(plan.cost(heuristic), evaluate(&plan, heuristic));
+            }
+        }
+        assert!(!plan.exact_costs.is_empty());
+
+        for id in plan.reachable_states() {
+            let mut reset = plan.clone();
+            if reset.reset(id).is_ok() {
+                for heuristic in heuristics {
+                    assert_eq!(reset.cost(heuristic), evaluate(&reset, heuristic));
+                }
+            }
+        }
+        for undo in undos.into_iter().rev() {
+            plan.undo(undo);
+            for heuristic in heuristics {
+                assert_eq!(plan.cost(heuristic), evaluate(&plan, heuristic));
+            }
+        }
+    }
 }
@@ -0,0 +1,85 @@
+use crate::State;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// What following a plan costs: each throw and each map has a fixed cost, plus a cost for each unit
+/// of the state it is done in, for the mental arithmetic of large ranges. The default only counts
+/// the throws.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostModel {
+    pub throw: f64,
+    pub map: f64,
+    pub unit: f64,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel {
+            throw: 1.0,
+            map: 0.0,
+            unit: 0.0,
+        }
+    }
+}
+
+impl CostModel {
+    pub fn throw_cost(&self, state: State) -> f64 {
+        self.throw + self.unit * state.units as f64
+    }
+
+    pub fn map_cost(&self, state: State) -> f64 {
+        self.map + self.unit * state.units as f64
+    }
+}
+
+/// `<throw>,<map>,<unit>`
+impl FromStr for CostModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let weights = s
+            .split(',')
+            .map(|weight| weight.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("Invalid cost model {}", s))?;
+        match weights[..] {
+            // Throws that cost nothing would make a plan that never ends the cheapest one
+            [throw, map, unit]
+                if weights.iter().all(|&weight| weight >= 0.0) && throw + unit > 0.0 =>
+            {
+                Ok(CostModel { throw, map, unit })
+            }
+            _ => Err(format!("Invalid cost model {}", s)),
+        }
+    }
+}
+
+/// The same text as parsed
+impl Display for CostModel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{},{}", self.throw, self.map, self.unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let model = CostModel {
+            throw: 2.0,
+            map: 0.5,
+            unit: 0.125,
+        };
+        assert_eq!("2,0.5,0.125".parse(), Ok(model));
+        assert_eq!(model.to_string().parse(), Ok(model));
+        assert_eq!("1,0,0".parse(), Ok(CostModel::default()));
+        assert!("0,0,1".parse::<CostModel>().is_ok());
+        for text in [
+            "", "1", "1,2", "1,2,3,4", "1,x,3", "1,-1,0", "0,0,0", "0,1,0",
+        ] {
+            assert!(text.parse::<CostModel>().is_err(), "{}", text);
+        }
+    }
+}
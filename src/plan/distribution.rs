@@ -0,0 +1,314 @@
+use crate::plan::{Plan, PlanBranch};
+use crate::State;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// The distribution of the number of throws a plan takes
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThrowDistribution {
+    /// `pmf[k]` is the probability to take exactly `k` throws, up to the horizon
+    pub pmf: Vec<f64>,
+    pub mean: f64,
+    pub variance: f64,
+}
+
+/// The states of a plan as a Markov chain, where only throws take time
+struct Chain {
+    /// Where each state leads, with which probability. Maps only lead to states after them
+    transitions: Vec<Vec<(usize, f64)>>,
+    throws: Vec<bool>,
+    /// The throws after stopping in each state, which are not 0 for the pending states
+    delays: Vec<u32>,
+    /// Whether no state is pending
+    complete: bool,
+    start: usize,
+}
+
+impl Plan {
+    /// The distribution of the number of throws of a complete plan, with its probabilities exact
+    /// up to `horizon` throws. `None` if the plan has pending states.
+    pub fn throw_distribution(&self, horizon: u32) -> Option<ThrowDistribution> {
+        let mut plan = self.clone();
+        plan.inline_library();
+        let chain = plan.chain(false)?;
+        let (mean, variance) = plan.throw_moments();
+        Some(ThrowDistribution {
+            pmf: chain.pmf(horizon),
+            mean,
+            variance,
+        })
+    }
+
+    /// The probabilities of each number of throws up to `horizon` if the pending states were
+    /// solved with the fewest throws they can need, and whether the plan is complete so that they
+    /// are exact. The throws of any completion of the plan can only be more
+    pub(super) fn throw_pmf_bound(&self, horizon: u32) -> (Vec<f64>, bool) {
+        let mut plan = self.clone();
+        plan.inline_library();
+        let chain = plan.chain(true).unwrap();
+        (chain.pmf(horizon), chain.complete)
+    }
+
+    /// The variance of the number of throws of a complete plan. `None` if the plan has pending
+    /// states
+    pub(super) fn throw_variance(&self) -> Option<f64> {
+        let mut plan = self.clone();
+        plan.inline_library();
+        let mut ids = plan.reachable_states().into_iter();
+        let pending = ids.any(|id| matches!(plan.plans[&id], PlanBranch::Pending { .. }));
+        (!pending).then(|| plan.throw_moments().1)
+    }
+
+    /// The mean and the variance of the throws of a complete plan without library references. The
+    /// first two moments from each state solve `m1 = P m1 + t` and `m2 = P m2 + t + 2 P_throw m1`,
+    /// where `t` tells the states that throw, so both are expected sums over the states of the plan
+    fn throw_moments(&self) -> (f64, f64) {
+        let mut from = vec![self.start];
+        let mut throws = HashMap::new();
+        for id in self.reachable_states() {
+            if let PlanBranch::Throw { next } = self.plans[&id] {
+                throws.insert(id, from.len());
+                from.push(next);
+            }
+        }
+
+        let first = self.expected_sums(&from, |id| match throws.contains_key(&id) {
+            true => 1.0,
+            false => 0.0,
+        });
+        let second = self.expected_sums(&[self.start], |id| match throws.get(&id) {
+            Some(&next) => 1.0 + 2.0 * first[next],
+            None => 0.0,
+        });
+
+        let mean = first[0];
+        (mean, second[0] - mean * mean)
+    }
+
+    /// `None` if a state refers to a library, or is pending unless pending states stop the chain
+    fn chain(&self, stop_pending: bool) -> Option<Chain> {
+        // Maps lead to fewer units or a smaller target, so they never loop without throwing
+        let mut ids = self.reachable_states();
+        ids.sort_by_key(|&id| {
+            let state = self.state(id);
+            (Reverse(state.target), Reverse(state.units))
+        });
+        let index = ids
+            .iter()
+            .enumerate()
+            .map(|(i, &id)| (id, i))
+            .collect::<HashMap<_, _>>();
+
+        let mut transitions = Vec::with_capacity(ids.len());
+        for &id in &ids {
+            let state = self.state(id);
+            transitions.push(match self.plans[&id] {
+                PlanBranch::Pending { .. } if stop_pending => vec![],
+                PlanBranch::Pending { .. } | PlanBranch::Library => return None,
+                PlanBranch::Solved => vec![],
+                PlanBranch::Throw { next } => vec![(index[&next], 1.0)],
+                PlanBranch::Map {
+                    units,
+                    sub_problem,
+                    remaining: None,
+                } => {
+                    debug_assert_eq!(units, state.units);
+                    vec![(index[&sub_problem], 1.0)]
+                }
+                PlanBranch::Map {
+                    units,
+                    sub_problem,
+                    remaining: Some(remaining),
+                } => {
+                    let ratio = units as f64 / state.units as f64;
+                    vec![
+                        (index[&sub_problem], ratio),
+                        (index[&remaining], 1.0 - ratio),
+                    ]
+                }
+            });
+        }
+
+        Some(Chain {
+            complete: ids
+                .iter()
+                .all(|id| !matches!(self.plans[id], PlanBranch::Pending { .. })),
+            delays: ids
+                .iter()
+                .map(|&id| match self.plans[&id] {
+                    PlanBranch::Pending { .. } => min_throws(self.state(id)),
+                    _ => 0,
+                })
+                .collect(),
+            transitions,
+            throws: ids
+                .iter()
+                .map(|id| matches!(self.plans[id], PlanBranch::Throw { .. }))
+                .collect(),
+            start: index[&self.start],
+        })
+    }
+}
+
+impl Chain {
+    fn pmf(&self, horizon: u32) -> Vec<f64> {
+        let n = self.transitions.len();
+        let mut pmf = vec![0.0; horizon as usize + 1];
+        let mut mass = vec![0.0; n];
+        mass[self.start] = 1.0;
+        for throws in 0..=horizon as usize {
+            // Map in order, so that each state gets all its mass before passing it on
+            for i in 0..n {
+                if self.transitions[i].is_empty() {
+                    if let Some(p) = pmf.get_mut(throws + self.delays[i] as usize) {
+                        *p += mass[i];
+                    }
+                    mass[i] = 0.0;
+                } else if !self.throws[i] {
+                    for &(next, probability) in &self.transitions[i] {
+                        mass[next] += probability * mass[i];
+                    }
+                    mass[i] = 0.0;
+                }
+            }
+
+            let mut thrown = vec![0.0; n];
+            for i in (0..n).filter(|&i| self.throws[i]) {
+                thrown[self.transitions[i][0].0] += mass[i];
+            }
+            mass = thrown;
+        }
+        pmf
+    }
+}
+
+/// The fewest throws to solve a state: a throw multiplies the units by the source, and a map
+/// divides the target by at most the units
+fn min_throws(state: State) -> u32 {
+    let mut throws = 0;
+    let mut units = state.units as u64;
+    while units < state.target as u64 && state.source > 1 {
+        units *= state.source as u64;
+        throws += 1;
+    }
+    throws
+}
+
+impl ThrowDistribution {
+    pub fn horizon(&self) -> u32 {
+        self.pmf.len() as u32 - 1
+    }
+
+    /// The probability to take more than `throws` throws, if within the horizon
+    pub fn tail(&self, throws: u32) -> Option<f64> {
+        tail(&self.pmf, throws)
+    }
+
+    /// The smallest number of throws that is enough with at least this probability, if within the
+    /// horizon
+    pub fn quantile(&self, probability: f64) -> Option<u32> {
+        quantile(&self.pmf, probability)
+    }
+}
+
+pub(super) fn tail(pmf: &[f64], throws: u32) -> Option<f64> {
+    (throws < pmf.len() as u32).then(|| 1.0 - pmf[..=throws as usize].iter().sum::<f64>())
+}
+
+pub(super) fn quantile(pmf: &[f64], probability: f64) -> Option<u32> {
+    let mut cumulative = 0.0;
+    for (throws, p) in pmf.iter().enumerate() {
+        cumulative += p;
+        // Sums of probabilities that should reach 1 may fall short by a rounding error
+        if cumulative >= probability - 1e-12 {
+            return Some(throws as u32);
+        }
+    }
+    None
+}
+
+/// The summary, then the probabilities of each number of throws
+impl Display for ThrowDistribution {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:<16}{:>16.6}", "mean", self.mean)?;
+        writeln!(f, "{:<16}{:>16.6}", "variance", self.variance)?;
+        writeln!(f, "{:<16}{:>16.6}", "std dev", self.variance.sqrt())?;
+        for probability in [0.5, 0.9, 0.99] {
+            let quantile = self.quantile(probability);
+            let quantile = quantile.map_or_else(|| String::from("-"), |q| q.to_string());
+            let name = format!("{}% quantile", probability * 100.0);
+            writeln!(f, "{:<16}{:>16}", name, quantile)?;
+        }
+        writeln!(
+            f,
+            "{:<16}{:>16}{:>16}",
+            "throws", "probability", "more throws"
+        )?;
+        for throws in 0..=self.horizon() {
+            let p = self.pmf[throws as usize];
+            let tail = self.tail(throws).unwrap();
+            writeln!(f, "{:<16}{:>16.6}{:>16.6}", throws, p, tail)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{assert_close, best};
+    use crate::{naive_solver, Action, State};
+
+    #[test]
+    fn geometric() {
+        // Two throws for 4 units, which fail with a probability of 1/4
+        let plan = naive_solver(State::new(2, 3));
+        let distribution = plan.throw_distribution(10).unwrap();
+
+        assert_eq!(distribution.horizon(), 10);
+        for (throws, &p) in distribution.pmf.iter().enumerate() {
+            let expected = match throws {
+                0 => 0.0,
+                _ if throws % 2 == 1 => 0.0,
+                _ => 0.75 * 0.25f64.powi(throws as i32 / 2 - 1),
+            };
+            assert_close(p, expected);
+        }
+        assert_close(distribution.mean, 8.0 / 3.0);
+        assert_close(distribution.variance, 16.0 / 9.0);
+        assert_close(distribution.tail(4).unwrap(), 1.0 / 16.0);
+        assert_eq!(distribution.tail(11), None);
+        assert_eq!(distribution.quantile(0.75), Some(2));
+        assert_eq!(distribution.quantile(0.76), Some(4));
+        assert_eq!(distribution.quantile(1.0), None);
+    }
+
+    #[test]
+    fn consistent_with_cost() {
+        for (source, target) in [(6, 4), (2, 5), (6, 5), (6, 3), (3, 2)] {
+            let plan = best(State::new(source, target));
+            let distribution = plan.throw_distribution(200).unwrap();
+            let cost = plan.exact_cost().unwrap();
+
+            assert_eq!(distribution.mean, cost);
+            assert_close(distribution.pmf.iter().sum(), 1.0);
+            let mean = distribution
+                .pmf
+                .iter()
+                .enumerate()
+                .map(|(throws, p)| throws as f64 * p)
+                .sum::<f64>();
+            assert!((mean - cost).abs() < 1e-9, "{} != {}", mean, cost);
+            assert!(distribution.variance >= 0.0);
+        }
+    }
+
+    #[test]
+    fn incomplete() {
+        let mut plan = Plan::new(State::new(6, 4));
+        assert_eq!(plan.throw_distribution(5), None);
+        plan.apply(plan.start(), Action::Throw).unwrap();
+        assert_eq!(plan.throw_distribution(5), None);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::plan::{Plan, PlanBranch, StateId};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Each reachable state is described by `units`, `target`, a tag for its branch and the branch
+/// fields, padded with zeros
+const ENTRY_LEN: usize = 8;
+
+/// A canonical description of the part of a plan that is reachable from its start. It is cheaper to
+/// build and smaller to keep than the textual representation of the plan.
+///
+/// Only a precomputed hash is fed to hashers, but equality compares the whole description, so that
+/// a hash collision never makes two different plans equal.
+#[derive(Debug, Clone, Eq)]
+pub struct PlanFingerprint {
+    hash: u64,
+    description: Box<[u32]>,
+}
+
+impl Plan {
+    pub fn fingerprint(&self) -> PlanFingerprint {
+        let mut entries = self
+            .reachable_states()
+            .into_iter()
+            .map(|id| self.encode_entry(id))
+            .collect::<Vec<_>>();
+        entries.sort_unstable();
+
+        let start = self.state(self.start);
+        let mut description = Vec::with_capacity(3 + entries.len() * ENTRY_LEN);
+        description.extend([start.source, start.units, start.target]);
+        description.extend(entries.into_iter().flatten());
+
+        PlanFingerprint::from_description(description.into())
+    }
+
+    /// Describe a state by its value rather than its id, so that the description does not depend
+    /// on the order in which states were interned
+    fn encode_entry(&self, id: StateId) -> [u32; ENTRY_LEN] {
+        let state = self.state(id);
+        let [units, target] = [state.units, state.target];
+        match self.plans[&id] {
+            PlanBranch::Solved => [units, target, 0, 0, 0, 0, 0, 0],
+            PlanBranch::Pending { min_map_units } => [units, target, 1, min_map_units, 0, 0, 0, 0],
+            PlanBranch::Library => [units, target, 4, 0, 0, 0, 0, 0],
+            PlanBranch::Throw { next } => {
+                let next = self.state(next);
+                [units, target, 2, next.units, next.target, 0, 0, 0]
+            }
+            PlanBranch::Map {
+                units: map_units,
+                sub_problem,
+                remaining,
+            } => {
+                let sub_problem = self.state(sub_problem);
+                let (remaining_units, remaining_target) = remaining.map_or((0, 0), |remaining| {
+                    let remaining = self.state(remaining);
+                    (remaining.units, remaining.target)
+                });
+                [
+                    units,
+                    target,
+                    3,
+                    map_units,
+                    sub_problem.units,
+                    sub_problem.target,
+                    remaining_units,
+                    remaining_target,
+                ]
+            }
+        }
+    }
+}
+
+impl PlanFingerprint {
+    pub fn from_description(description: Box<[u32]>) -> Self {
+        let mut hasher = DefaultHasher::new();
+        description.hash(&mut hasher);
+
+        PlanFingerprint {
+            hash: hasher.finish(),
+            description,
+        }
+    }
+
+    pub fn description(&self) -> &[u32] {
+        &self.description
+    }
+}
+
+impl PartialEq for PlanFingerprint {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.description == other.description
+    }
+}
+
+impl Hash for PlanFingerprint {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+/// Plans are equal when the parts reachable from their starts are the same, whatever their
+/// interners and unreachable states
+impl PartialEq for Plan {
+    fn eq(&self, other: &Self) -> bool {
+        self.fingerprint() == other.fingerprint()
+    }
+}
+
+impl Eq for Plan {}
+
+impl Hash for Plan {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.fingerprint().hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Action, State};
+    use std::collections::HashSet;
+
+    #[test]
+    fn same_plan_in_any_order() {
+        let start = State::new(6, 4);
+        let six = State {
+            source: 6,
+            target: 4,
+            units: 6,
+        };
+        let four = State {
+            source: 6,
+            target: 4,
+            units: 4,
+        };
+
+        let mut plan_a = Plan::new(start);
+        plan_a.apply(plan_a.start(), Action::Throw).unwrap();
+        plan_a
+            .apply(plan_a.interner.get(six).unwrap(), Action::Map(2))
+            .unwrap();
+        plan_a
+            .apply(plan_a.interner.get(four).unwrap(), Action::Map(4))
+            .unwrap();
+
+        // With another interner, the ids are different
+        let mut plan_b = Plan::new(start);
+        plan_b.interner.intern(four);
+        plan_b.apply(plan_b.start(), Action::Throw).unwrap();
+        plan_b
+            .apply(plan_b.interner.get(six).unwrap(), Action::Map(2))
+            .unwrap();
+        let plan_c = plan_b.clone();
+        plan_b
+            .apply(plan_b.interner.get(four).unwrap(), Action::Throw)
+            .unwrap();
+
+        assert_eq!(plan_a.fingerprint(), plan_a.clone().fingerprint());
+        assert_ne!(plan_a.fingerprint(), plan_b.fingerprint());
+        assert_ne!(plan_a.fingerprint(), plan_c.fingerprint());
+
+        // Deciding the pending states in another order gives the same plan
+        let sub_problem = State {
+            source: 6,
+            target: 2,
+            units: 1,
+        };
+        let mut plan_e = plan_a.clone();
+        plan_e
+            .apply(plan_e.interner.get(sub_problem).unwrap(), Action::Throw)
+            .unwrap();
+        let mut plan_f = plan_c.clone();
+        plan_f
+            .apply(plan_f.interner.get(sub_problem).unwrap(), Action::Throw)
+            .unwrap();
+        plan_f
+            .apply(plan_f.interner.get(four).unwrap(), Action::Map(4))
+            .unwrap();
+        assert_eq!(plan_e.fingerprint(), plan_f.fingerprint());
+
+        // Unreachable states are ignored
+        let mut plan_d = plan_a.clone();
+        let three = plan_d.interner.intern(State {
+            source: 6,
+            target: 4,
+            units: 3,
+        });
+        plan_d.plans.insert(three, PlanBranch::Solved);
+        assert_eq!(plan_a.fingerprint(), plan_d.fingerprint());
+
+        let visited = HashSet::from([plan_a.fingerprint(), plan_b.fingerprint()]);
+        assert!(visited.contains(&plan_d.fingerprint()));
+        assert!(!visited.contains(&plan_c.fingerprint()));
+
+        assert_eq!(plan_a, plan_d);
+        assert_eq!(plan_e, plan_f);
+        assert_ne!(plan_a, plan_b);
+        let plans = HashSet::from([plan_a, plan_b]);
+        assert!(plans.contains(&plan_d));
+        assert!(!plans.contains(&plan_c));
+    }
+
+    #[test]
+    fn collisions_are_not_equal() {
+        let a = PlanFingerprint {
+            hash: 17,
+            description: vec![1, 2, 3].into(),
+        };
+        let b = PlanFingerprint {
+            hash: 17,
+            description: vec![1, 2, 4].into(),
+        };
+        assert_ne!(a, b);
+        assert_eq!(HashSet::from([a, b]).len(), 2);
+    }
+}
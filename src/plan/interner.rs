@@ -0,0 +1,182 @@
+use crate::State;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+use std::sync::RwLock;
+
+/// How many states the first chunk of an interner holds. Each next chunk holds twice as many
+const FIRST_CHUNK_LEN: usize = 64;
+
+/// Enough chunks for every `u32` id
+const CHUNKS: usize = 27;
+
+/// A compact reference to a state, given by a [`StateInterner`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct StateId(u32);
+
+/// Gives dense ids to the states of a problem, in the order they are first seen.
+///
+/// An interner is shared by all the plans of a search, so that their ids can be compared, and is
+/// safe to share between threads. Ids are never forgotten, so a state keeps its id even when it is
+/// removed from a plan and added again later.
+///
+/// The states are stored in chunks that are never moved nor freed before the interner, so that
+/// looking up the state of an id, which the solvers do all the time, takes no lock.
+#[derive(Debug, Default)]
+pub struct StateInterner {
+    inner: RwLock<Interned>,
+    /// The first slot of each chunk of [`Interned::chunks`], null until it is allocated
+    chunk_starts: [AtomicPtr<Slot>; CHUNKS],
+}
+
+#[derive(Debug, Default)]
+struct Interned {
+    ids: HashMap<State, StateId>,
+    chunks: Vec<Box<[Slot]>>,
+    len: usize,
+}
+
+/// A state, written once before its id is given
+#[derive(Debug, Default)]
+struct Slot {
+    source: AtomicU32,
+    target: AtomicU32,
+    units: AtomicU32,
+}
+
+impl StateId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl StateInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&self, state: State) -> StateId {
+        if let Some(id) = self.get(state) {
+            return id;
+        }
+
+        let mut inner = self.inner.write().unwrap();
+        if let Some(&id) = inner.ids.get(&state) {
+            return id;
+        }
+
+        let index = inner.len;
+        let id = StateId(u32::try_from(index).expect("Too many states"));
+        let (chunk, offset) = position(index);
+        if chunk == inner.chunks.len() {
+            let slots = (0..FIRST_CHUNK_LEN << chunk)
+                .map(|_| Slot::default())
+                .collect::<Box<[_]>>();
+            self.chunk_starts[chunk].store(slots.as_ptr() as *mut Slot, Ordering::Release);
+            inner.chunks.push(slots);
+        }
+        inner.chunks[chunk][offset].store(state);
+        inner.len += 1;
+        inner.ids.insert(state, id);
+        id
+    }
+
+    pub fn get(&self, state: State) -> Option<StateId> {
+        self.inner.read().unwrap().ids.get(&state).copied()
+    }
+
+    pub fn state(&self, id: StateId) -> State {
+        let (chunk, offset) = position(id.index());
+        let start = self.chunk_starts[chunk].load(Ordering::Acquire);
+        assert!(!start.is_null(), "Unknown state id {}", id.index());
+        // SAFETY: the chunk is owned by `inner`, which never moves nor frees it while the interner
+        // lives, and `offset` is within it. Its slots are only written through shared references
+        let slot = unsafe { &*start.add(offset) };
+        slot.load()
+    }
+
+    /// All the interned states, in the order of their ids
+    pub fn states(&self) -> Vec<State> {
+        let len = self.inner.read().unwrap().len;
+        (0..len)
+            .map(|index| self.state(StateId(index as u32)))
+            .collect()
+    }
+}
+
+impl Slot {
+    fn store(&self, state: State) {
+        self.source.store(state.source, Ordering::Relaxed);
+        self.target.store(state.target, Ordering::Relaxed);
+        self.units.store(state.units, Ordering::Relaxed);
+    }
+
+    /// The id of the slot is only given after the state is stored, and passing it to another
+    /// thread orders the store before this load
+    fn load(&self) -> State {
+        State {
+            source: self.source.load(Ordering::Relaxed),
+            target: self.target.load(Ordering::Relaxed),
+            units: self.units.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The chunk of the state with this index, and its position in the chunk
+fn position(index: usize) -> (usize, usize) {
+    let chunk = (usize::BITS - 1 - (index / FIRST_CHUNK_LEN + 1).leading_zeros()) as usize;
+    (chunk, index - FIRST_CHUNK_LEN * ((1 << chunk) - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dense_ids() {
+        let interner = StateInterner::new();
+        let states = [State::new(6, 4), State::new(6, 2), State::new(6, 1)];
+
+        for (i, &state) in states.iter().enumerate() {
+            assert_eq!(interner.get(state), None);
+            assert_eq!(interner.intern(state).index(), i);
+        }
+        for (i, &state) in states.iter().enumerate() {
+            assert_eq!(interner.intern(state).index(), i);
+            assert_eq!(interner.state(StateId(i as u32)), state);
+        }
+        assert_eq!(interner.states(), states);
+    }
+
+    #[test]
+    fn many_states() {
+        let interner = StateInterner::new();
+        let states = (1..=1000)
+            .map(|units| State {
+                source: 6,
+                target: 7,
+                units,
+            })
+            .collect::<Vec<_>>();
+
+        // Threads intern and read states concurrently, across many chunks
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for &state in &states {
+                        assert_eq!(interner.state(interner.intern(state)), state);
+                    }
+                });
+            }
+        });
+        assert_eq!(interner.states().len(), states.len());
+        for (index, chunk, offset) in [
+            (0, 0, 0),
+            (63, 0, 63),
+            (64, 1, 0),
+            (191, 1, 127),
+            (192, 2, 0),
+        ] {
+            assert_eq!(position(index), (chunk, offset));
+        }
+    }
+}
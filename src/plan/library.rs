@@ -0,0 +1,213 @@
+use crate::plan::{CostModel, Plan, PlanBranch, StateId};
+use crate::State;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+/// Complete plans for problems starting from a single unit, keyed by their source and target,
+/// that plans can refer to with [`PlanBranch::Library`] instead of solving them again. It is safe
+/// to share between threads, and between the plans of different problems.
+///
+/// All the plans have the cost model of the library, and an entry never changes once added, so
+/// that the plans referring to it always cost the same.
+#[derive(Debug)]
+pub struct PlanLibrary {
+    cost_model: CostModel,
+    entries: RwLock<BTreeMap<(u32, u32), LibraryEntry>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LibraryEntry {
+    pub plan: Arc<Plan>,
+    pub cost: f64,
+}
+
+impl PlanLibrary {
+    pub fn new(cost_model: CostModel) -> Self {
+        PlanLibrary {
+            cost_model,
+            entries: RwLock::default(),
+        }
+    }
+
+    pub fn cost_model(&self) -> CostModel {
+        self.cost_model
+    }
+
+    /// Add a complete plan, unless the library already has a plan for its start. Returns whether
+    /// the plan was added. The references of the plan to a library are inlined, so that entries
+    /// stand on their own
+    pub fn insert(&self, mut plan: Plan) -> bool {
+        let start = plan.state(plan.start);
+        assert_eq!(start.units, 1, "Library plans start from a single unit");
+        assert_eq!(
+            plan.cost_model, self.cost_model,
+            "Library plans must have the cost model of the library"
+        );
+        plan.inline_library();
+        plan.prune_unreachable();
+        let cost = plan.exact_cost().expect("Library plans must be complete");
+
+        let mut entries = self.entries.write().unwrap();
+        match entries.entry((start.source, start.target)) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(vacant) => {
+                vacant.insert(LibraryEntry {
+                    plan: Arc::new(plan),
+                    cost,
+                });
+                true
+            }
+        }
+    }
+
+    /// The entry for the state, which must start from a single unit to have one
+    pub fn get(&self, state: State) -> Option<LibraryEntry> {
+        if state.units != 1 {
+            return None;
+        }
+        let entries = self.entries.read().unwrap();
+        entries.get(&(state.source, state.target)).cloned()
+    }
+}
+
+impl Plan {
+    /// Refer to the library for every sub-problem it has a plan for, instead of deciding it in
+    /// this plan, so that the sub-problems cost what the library plans cost. The sub-plans they
+    /// replace are pruned like any other unreachable states. The library must have the cost model
+    /// of the plan.
+    pub fn use_library(&mut self, library: Arc<PlanLibrary>) {
+        assert_eq!(
+            library.cost_model, self.cost_model,
+            "The library has another cost model"
+        );
+        if self.library.is_some() {
+            self.inline_library();
+        }
+
+        for id in self.reachable_states() {
+            if id != self.start
+                && self.plans.contains_key(&id)
+                && library.get(self.state(id)).is_some()
+            {
+                self.set_branch(id, PlanBranch::Library);
+            }
+        }
+        self.library = Some(library);
+    }
+
+    /// Replace the references to the library by the branches of its plans
+    pub fn inline_library(&mut self) {
+        let library = match self.library.take() {
+            Some(library) => library,
+            None => return,
+        };
+
+        for id in self.reachable_states() {
+            // Inlining an earlier entry may have decided the state already
+            if let Some(PlanBranch::Library) = self.plans.get(&id) {
+                let entry = library
+                    .get(self.state(id))
+                    .unwrap_or_else(|| panic!("{} is not in the library", self.state(id)));
+                self.set_branch(id, PlanBranch::Pending { min_map_units: 2 });
+                self.graft(id, &entry.plan).unwrap();
+            }
+        }
+    }
+
+    /// The states of the plan that refer to the library, in the order they are reached
+    pub(super) fn library_references(&self) -> Vec<StateId> {
+        self.reachable_states()
+            .into_iter()
+            .filter(|id| matches!(self.plans.get(id), Some(PlanBranch::Library)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::naive_solver;
+    use crate::test_util::best;
+
+    #[test]
+    fn refer_and_inline() {
+        let library = Arc::new(PlanLibrary::new(CostModel::default()));
+        assert!(library.insert(best(State::new(6, 2))));
+        // Entries never change
+        assert!(!library.insert(naive_solver(State::new(6, 2))));
+        assert_eq!(
+            library.get(State::new(6, 2)).unwrap().cost,
+            best(State::new(6, 2)).exact_cost().unwrap()
+        );
+        assert!(library.get(State::new(6, 3)).is_none());
+
+        let plan = best(State::new(6, 4));
+        let mut shared = plan.clone();
+        shared.use_library(library.clone());
+        assert!(shared.plans().len() < plan.plans().len());
+        assert_eq!(shared.library_references().len(), 1);
+        assert_eq!(shared.validate(), Ok(()));
+        assert_eq!(shared.exact_cost(), plan.exact_cost());
+        assert!(shared.to_string().contains("1/2 -> from the library"));
+
+        let mut inlined = shared.clone();
+        inlined.inline_library();
+        assert!(inlined.library.is_none());
+        assert_eq!(inlined, plan);
+
+        // Entries stand on their own
+        library.insert(shared);
+        let entry = library.get(State::new(6, 4)).unwrap();
+        assert!(entry.plan.library.is_none());
+        assert_eq!(*entry.plan, plan);
+    }
+
+    #[test]
+    fn missing_entry() {
+        let mut plan = best(State::new(6, 4));
+        let library = Arc::new(PlanLibrary::new(CostModel::default()));
+        library.insert(best(State::new(6, 2)));
+        plan.use_library(library);
+
+        // A library that lost the plan of a sub-problem, or a copy without one
+        let mut missing = Plan::from_branches(
+            plan.interner.clone(),
+            plan.start,
+            plan.plans().iter().map(|(&id, &branch)| (id, branch)),
+        );
+        let sub_problem = State::new(6, 2);
+        assert_eq!(
+            missing.validate(),
+            Err(vec![crate::plan::PlanProblem::NotInLibrary {
+                state: sub_problem
+            }])
+        );
+        assert!(missing.cost(|_| 7.0).estimated);
+    }
+
+    #[test]
+    fn cost_model() {
+        let cost_model = CostModel {
+            throw: 1.0,
+            map: 0.0,
+            unit: 0.5,
+        };
+        let library = Arc::new(PlanLibrary::new(cost_model));
+        let mut sub_plan = best(State::new(6, 2));
+        sub_plan.set_cost_model(cost_model);
+        library.insert(sub_plan);
+
+        let mut plan = best(State::new(6, 4));
+        plan.set_cost_model(cost_model);
+        let cost = plan.exact_cost();
+        plan.use_library(library);
+        assert_eq!(plan.library_references().len(), 1);
+        assert_eq!(plan.exact_cost(), cost);
+
+        // The costs of the library no longer apply with another model
+        plan.set_cost_model(CostModel::default());
+        assert!(plan.library.is_none());
+        assert_eq!(plan.exact_cost(), best(State::new(6, 4)).exact_cost());
+    }
+}
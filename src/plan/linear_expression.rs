@@ -0,0 +1,177 @@
+use std::fmt::{Display, Formatter};
+
+/// `bias + sum(weight * variable)`, where the variables are unknown costs
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinearExpression<V> {
+    bias: f64,
+    /// Sorted by variable. Costs have few variables, so this is faster than a map
+    weights: Vec<(V, f64)>,
+}
+
+impl<V: Ord + Copy> LinearExpression<V> {
+    pub fn constant(bias: f64) -> Self {
+        LinearExpression {
+            bias,
+            weights: Vec::new(),
+        }
+    }
+
+    pub fn variable(variable: V) -> Self {
+        LinearExpression {
+            bias: 0.0,
+            weights: vec![(variable, 1.0)],
+        }
+    }
+
+    /// The value of the expression if it depends on no variable
+    pub fn as_constant(&self) -> Option<f64> {
+        self.weights.is_empty().then_some(self.bias)
+    }
+
+    pub fn add(mut self, other: &Self) -> Self {
+        self.bias += other.bias;
+        for &(variable, weight) in &other.weights {
+            *self.weight_mut(variable) += weight;
+        }
+        self
+    }
+
+    pub fn mul(mut self, factor: f64) -> Self {
+        self.bias *= factor;
+        for (_, weight) in &mut self.weights {
+            *weight *= factor;
+        }
+        self
+    }
+
+    /// Solve the equation `self = variable` for `variable`
+    pub fn solve_equals_to(mut self, variable: V) -> Self {
+        match self.remove(variable) {
+            // `self` is independent
+            None => self,
+            // `weight * variable + independent = variable`, so
+            // `variable = independent / (1 - weight)`
+            Some(weight) => {
+                self.bias /= 1.0 - weight;
+                for (_, other) in &mut self.weights {
+                    *other /= 1.0 - weight;
+                }
+                self
+            }
+        }
+    }
+
+    /// The same expression with other variables, the weights of the variables that become the same
+    /// being added
+    pub fn map_variables<W: Ord + Copy>(self, mut f: impl FnMut(V) -> W) -> LinearExpression<W> {
+        let mut mapped = LinearExpression::constant(self.bias);
+        for (variable, weight) in self.weights {
+            *mapped.weight_mut(f(variable)) += weight;
+        }
+        mapped
+    }
+
+    fn weight_mut(&mut self, variable: V) -> &mut f64 {
+        let index = match self.weights.binary_search_by_key(&variable, |&(v, _)| v) {
+            Ok(index) => index,
+            Err(index) => {
+                self.weights.insert(index, (variable, 0.0));
+                index
+            }
+        };
+        &mut self.weights[index].1
+    }
+
+    fn remove(&mut self, variable: V) -> Option<f64> {
+        let index = self
+            .weights
+            .binary_search_by_key(&variable, |&(v, _)| v)
+            .ok()?;
+        Some(self.weights.remove(index).1)
+    }
+}
+
+/// Like `1.5 + [a] - 0.5 [b]`, with the variables in order and `0` for an empty expression
+impl<V: Display> Display for LinearExpression<V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut first = true;
+        if self.bias != 0.0 {
+            write!(f, "{}", self.bias)?;
+            first = false;
+        }
+
+        for (variable, weight) in &self.weights {
+            let weight = *weight;
+            if weight == 0.0 {
+                continue;
+            }
+            let abs = match first {
+                true if weight < 0.0 => {
+                    write!(f, "- ")?;
+                    -weight
+                }
+                true => weight,
+                false => {
+                    write!(f, " {} ", if weight < 0.0 { '-' } else { '+' })?;
+                    weight.abs()
+                }
+            };
+            if abs != 1.0 {
+                write!(f, "{} ", abs)?;
+            }
+            write!(f, "[{}]", variable)?;
+            first = false;
+        }
+
+        if first {
+            write!(f, "0")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Expression = LinearExpression<char>;
+
+    // Only the tests substitute variables, here and in the tests of the costs
+    impl<V: Ord + Copy> LinearExpression<V> {
+        /// Replace the variable by another expression
+        pub(crate) fn substitute(mut self, variable: V, expression: &Self) -> Self {
+            match self.remove(variable) {
+                None => self,
+                Some(weight) => self.add(&expression.clone().mul(weight)),
+            }
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = Expression::variable('a');
+        let b = Expression::variable('b');
+        let expression = Expression::constant(3.0).add(&a.clone().mul(2.0)).add(&b);
+        assert_eq!(expression.as_constant(), None);
+        assert_eq!(expression.to_string(), "3 + 2 [a] + [b]");
+        assert_eq!(expression.clone().mul(-1.0).to_string(), "-3 - 2 [a] - [b]");
+        assert_eq!(b.clone().mul(-0.5).add(&a).to_string(), "[a] - 0.5 [b]");
+        assert_eq!(a.clone().mul(-1.0).to_string(), "- [a]");
+        assert_eq!(a.mul(0.0).to_string(), "0");
+
+        // `a = 3 + 2 a + b` gives `a = -3 - b`
+        let solved = expression.clone().solve_equals_to('a');
+        assert_eq!(solved.to_string(), "-3 - [b]");
+        assert_eq!(solved.clone().solve_equals_to('c'), solved);
+
+        let substituted = expression.substitute('b', &Expression::constant(4.0));
+        assert_eq!(substituted.to_string(), "7 + 2 [a]");
+        let value = substituted
+            .clone()
+            .substitute('a', &Expression::constant(0.5));
+        assert_eq!(value.as_constant(), Some(8.0));
+
+        let merged = substituted.add(&b).map_variables(|_| 'x');
+        assert_eq!(merged.to_string(), "7 + 3 [x]");
+    }
+}
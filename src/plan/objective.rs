@@ -0,0 +1,247 @@
+use crate::plan::distribution::{quantile, tail};
+use crate::plan::{Plan, PlanCost};
+use crate::State;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// What a solver minimizes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Objective {
+    /// The expected cost, which is the expected number of throws with the default
+    /// [`crate::plan::CostModel`]
+    ExpectedThrows,
+    /// The variance of the number of throws, among the plans with the fewest expected throws. As
+    /// partial plans have no bound of their variance, the search goes through all those plans
+    Variance,
+    /// The number of throws that is enough with this probability, or `horizon + 1` if more are
+    /// needed
+    Quantile { probability: f64, horizon: u32 },
+    /// The probability to need more than this number of throws
+    Tail(u32),
+}
+
+/// The horizon of a quantile when it is not given
+const DEFAULT_HORIZON: u32 = 100;
+
+impl Plan {
+    /// Evaluate the objective for the plan. For the expected throws it is [`Plan::cost()`] with the
+    /// heuristic. For the others, the pending states are taken to need the fewest throws they can,
+    /// which gives a lower bound of any completion of the plan, except for the variance that has no
+    /// bound: it is 0 until the plan is complete.
+    pub fn objective_cost(
+        &mut self,
+        objective: Objective,
+        heuristic: impl FnMut(State) -> f64,
+    ) -> PlanCost {
+        let (value, estimated) = match objective {
+            Objective::ExpectedThrows => return self.cost(heuristic),
+            Objective::Variance => match self.throw_variance() {
+                Some(variance) => (variance, false),
+                None => (0.0, true),
+            },
+            Objective::Quantile {
+                probability,
+                horizon,
+            } => {
+                let (pmf, complete) = self.throw_pmf_bound(horizon);
+                let quantile = quantile(&pmf, probability).unwrap_or(horizon + 1);
+                (quantile as f64, !complete)
+            }
+            Objective::Tail(throws) => {
+                let (pmf, complete) = self.throw_pmf_bound(throws);
+                (tail(&pmf, throws).unwrap(), !complete)
+            }
+        };
+        PlanCost { estimated, value }
+    }
+}
+
+/// `expected`, `variance`, `quantile:<probability>[:<horizon>]` or `tail:<throws>`
+impl FromStr for Objective {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Unknown objective {}", s);
+        let mut parts = s.split(':');
+        let objective = match parts.next() {
+            Some("expected") => Objective::ExpectedThrows,
+            Some("variance") => Objective::Variance,
+            Some("quantile") => Objective::Quantile {
+                probability: parts
+                    .next()
+                    .and_then(|p| p.parse().ok())
+                    .ok_or_else(invalid)?,
+                horizon: match parts.next() {
+                    None => DEFAULT_HORIZON,
+                    Some(horizon) => horizon.parse().map_err(|_| invalid())?,
+                },
+            },
+            Some("tail") => Objective::Tail(
+                parts
+                    .next()
+                    .and_then(|t| t.parse().ok())
+                    .ok_or_else(invalid)?,
+            ),
+            _ => return Err(invalid()),
+        };
+        match parts.next() {
+            None => Ok(objective),
+            Some(_) => Err(invalid()),
+        }
+    }
+}
+
+/// The same text as parsed
+impl Display for Objective {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Objective::ExpectedThrows => write!(f, "expected"),
+            Objective::Variance => write!(f, "variance"),
+            Objective::Quantile {
+                probability,
+                horizon,
+            } => write!(f, "quantile:{}:{}", probability, horizon),
+            Objective::Tail(throws) => write!(f, "tail:{}", throws),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_order::NaturalOrder;
+    use crate::best_solver::best_solver;
+    use crate::divider::Divider;
+    use crate::naive_solver;
+    use crate::plan::CostModel;
+
+    #[test]
+    fn parse() {
+        for (text, objective) in [
+            ("expected", Objective::ExpectedThrows),
+            ("variance", Objective::Variance),
+            (
+                "quantile:0.95:20",
+                Objective::Quantile {
+                    probability: 0.95,
+                    horizon: 20,
+                },
+            ),
+            ("tail:3", Objective::Tail(3)),
+        ] {
+            assert_eq!(text.parse(), Ok(objective));
+            assert_eq!(objective.to_string(), text);
+        }
+        assert_eq!(
+            "quantile:0.5".parse(),
+            Ok(Objective::Quantile {
+                probability: 0.5,
+                horizon: DEFAULT_HORIZON
+            })
+        );
+        for text in [
+            "",
+            "mean",
+            "tail",
+            "tail:x",
+            "quantile",
+            "variance:1",
+            "tail:1:2",
+        ] {
+            assert!(text.parse::<Objective>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn partial_plans_are_bounds() {
+        let objectives = [
+            Objective::Tail(0),
+            Objective::Tail(2),
+            Objective::Quantile {
+                probability: 0.9,
+                horizon: 10,
+            },
+        ];
+        let divider = Divider::new();
+        let mut plan = Plan::new(State::new(2, 5));
+        // Alternate between throwing and mapping, which ends for a short plan
+        for step in 0..12 {
+            let Some((id, actions)) = plan.first_pending_actions(&divider, &NaturalOrder) else {
+                break;
+            };
+            let mut complete = plan.clone();
+            complete.complete_with(naive_solver);
+            for objective in objectives {
+                let bound = plan.objective_cost(objective, |_| 0.0);
+                let cost = complete.objective_cost(objective, |_| 0.0);
+                assert!(bound.estimated && !cost.estimated);
+                assert!(
+                    bound.value <= cost.value + 1e-12,
+                    "{} in\n{}",
+                    objective,
+                    plan
+                );
+            }
+            plan.apply(id, actions[step % actions.len()]).unwrap();
+        }
+    }
+
+    #[test]
+    fn best_for_objective() {
+        for (source, target) in [(6, 4), (2, 3), (2, 5), (6, 5)] {
+            let start = State::new(source, target);
+            let solve = |objective| {
+                best_solver(
+                    start,
+                    |_| 0.0,
+                    &NaturalOrder,
+                    objective,
+                    CostModel::default(),
+                    None,
+                )
+                .0
+            };
+            let expected = solve(Objective::ExpectedThrows);
+            for objective in [
+                Objective::Tail(2),
+                Objective::Quantile {
+                    probability: 0.95,
+                    horizon: 20,
+                },
+            ] {
+                let mut best = solve(objective);
+                let value = best.objective_cost(objective, |_| 0.0).value;
+                for mut other in [expected.clone(), naive_solver(start)] {
+                    assert!(value <= other.objective_cost(objective, |_| 0.0).value + 1e-12);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn variance_among_fewest_throws() {
+        for (source, target) in [(6, 4), (2, 3), (2, 5), (6, 5), (6, 3)] {
+            let start = State::new(source, target);
+            let solve = |objective| {
+                best_solver(
+                    start,
+                    |_| 0.0,
+                    &NaturalOrder,
+                    objective,
+                    CostModel::default(),
+                    None,
+                )
+                .0
+            };
+            let expected = solve(Objective::ExpectedThrows);
+            let best = solve(Objective::Variance);
+            assert!(best.exact_cost().unwrap() <= expected.exact_cost().unwrap() + 1e-9);
+            let variance = |plan: &Plan| {
+                plan.clone()
+                    .objective_cost(Objective::Variance, |_| 0.0)
+                    .value
+            };
+            assert!(variance(&best) <= variance(&expected) + 1e-12);
+        }
+    }
+}
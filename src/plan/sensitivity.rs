@@ -0,0 +1,187 @@
+use crate::plan::{CostModel, Plan};
+use std::fmt::{Display, Formatter};
+
+/// The cost of a complete plan as a function of the weights of the cost model, which it depends on
+/// linearly: `throw * throws + map * maps + unit * units`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostFunction {
+    /// The expected number of throws
+    pub throws: f64,
+    /// The expected number of maps
+    pub maps: f64,
+    /// The expected sum of the units of the states thrown or mapped in
+    pub units: f64,
+}
+
+/// How much the weights of the cost model can change before a plan stops being the cheapest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sensitivity {
+    pub cost: CostFunction,
+    /// For each weight, in the order of [`CostModel`], the values it can take with the other
+    /// weights unchanged while the plan costs no more than its alternatives. `None` if there are
+    /// none
+    pub ranges: [Option<(f64, f64)>; 3],
+}
+
+impl CostFunction {
+    pub fn cost(&self, cost_model: CostModel) -> f64 {
+        cost_model.throw * self.throws + cost_model.map * self.maps + cost_model.unit * self.units
+    }
+
+    fn coefficients(&self) -> [f64; 3] {
+        [self.throws, self.maps, self.units]
+    }
+}
+
+impl Plan {
+    /// The cost of the plan with any cost model, `None` if the plan is not complete
+    pub fn cost_function(&self) -> Option<CostFunction> {
+        let mut plan = self.clone();
+        let mut cost = |throw, map, unit| {
+            plan.set_cost_model(CostModel { throw, map, unit });
+            plan.exact_cost()
+        };
+        Some(CostFunction {
+            throws: cost(1.0, 0.0, 0.0)?,
+            maps: cost(0.0, 1.0, 0.0)?,
+            units: cost(0.0, 0.0, 1.0)?,
+        })
+    }
+
+    /// Compare the costs of the plan with those of alternative plans, around the cost model of the
+    /// plan. `None` if a plan is not complete
+    pub fn sensitivity(&self, alternatives: &[Plan]) -> Option<Sensitivity> {
+        let cost = self.cost_function()?;
+        let alternatives = alternatives
+            .iter()
+            .map(|plan| plan.cost_function())
+            .collect::<Option<Vec<_>>>()?;
+
+        let cost_model = self.cost_model;
+        let weights = [cost_model.throw, cost_model.map, cost_model.unit];
+        let mut ranges: [Option<(f64, f64)>; 3] = [Some((0.0, f64::INFINITY)); 3];
+        for alternative in alternatives {
+            // Costs computed in different ways differ by rounding errors
+            let difference = cost.cost(cost_model) - alternative.cost(cost_model) - 1e-12;
+            let coefficients = cost.coefficients();
+            let alternative_coefficients = alternative.coefficients();
+
+            for (k, range) in ranges.iter_mut().enumerate() {
+                // The difference is `difference + (weight - weights[k]) * slope`, which must stay
+                // at most 0
+                let slope = coefficients[k] - alternative_coefficients[k];
+                let (low, high) = match range {
+                    Some(range) => *range,
+                    None => continue,
+                };
+                let bound = weights[k] - difference / slope;
+                *range = if slope == 0.0 {
+                    Some((low, high)).filter(|_| difference <= 0.0)
+                } else if slope > 0.0 {
+                    Some((low, high.min(bound)))
+                } else {
+                    Some((low.max(bound), high))
+                }
+                .filter(|(low, high)| low <= high);
+            }
+        }
+
+        Some(Sensitivity { cost, ranges })
+    }
+}
+
+impl Display for CostFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} throw + {} map + {} unit",
+            self.throws, self.maps, self.units
+        )
+    }
+}
+
+/// The cost function, then the range of each weight
+impl Display for Sensitivity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "cost = {}", self.cost)?;
+        for (name, range) in ["throw", "map", "unit"].iter().zip(&self.ranges) {
+            match range {
+                Some((low, high)) => writeln!(f, "{} from {} to {}", name, low, high)?,
+                None => writeln!(f, "{} never", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_order::NaturalOrder;
+    use crate::pareto_solver::pareto_solver;
+    use crate::test_util::assert_close;
+    use crate::{naive_solver, State};
+
+    #[test]
+    fn cost_function() {
+        // 1/3 -> throw to 2/3 -> throw to 4/3 -> map 3 to 1/1 and 1/3, 4/3 times on average
+        let plan = naive_solver(State::new(2, 3));
+        let cost = plan.cost_function().unwrap();
+        assert_close(cost.throws, 8.0 / 3.0);
+        assert_close(cost.maps, 4.0 / 3.0);
+        assert_close(cost.units, 28.0 / 3.0);
+
+        let cost_model = CostModel {
+            throw: 2.0,
+            map: 0.5,
+            unit: 0.25,
+        };
+        let mut weighted = plan;
+        weighted.set_cost_model(cost_model);
+        assert_close(cost.cost(cost_model), weighted.exact_cost().unwrap());
+        assert_eq!(Plan::new(State::new(2, 3)).cost_function(), None);
+    }
+
+    #[test]
+    fn ranges() {
+        // The other plan of the frontier of 2/9 throws and maps more, but in smaller ranges
+        let start = State::new(2, 9);
+        let (frontier, _) = pareto_solver(start, |_| 0.0, &NaturalOrder, CostModel::default());
+        let best = &frontier[0].plan;
+        let alternatives = frontier[1..]
+            .iter()
+            .map(|point| point.plan.clone())
+            .collect::<Vec<_>>();
+        let sensitivity = best.sensitivity(&alternatives).unwrap();
+        let weights = [1.0, 0.0, 0.0];
+
+        let mut changes = 0;
+        for (k, range) in sensitivity.ranges.iter().enumerate() {
+            let (low, high) = range.unwrap();
+            assert!(low <= weights[k] && weights[k] <= high);
+            // Beyond the range, an alternative is cheaper
+            if high.is_finite() {
+                let mut changed = weights;
+                changed[k] = high + 0.01;
+                let cost_model = CostModel {
+                    throw: changed[0],
+                    map: changed[1],
+                    unit: changed[2],
+                };
+                let cost = sensitivity.cost.cost(cost_model);
+                assert!(alternatives.iter().any(|plan| plan
+                    .cost_function()
+                    .unwrap()
+                    .cost(cost_model)
+                    < cost));
+                changes += 1;
+            }
+        }
+        assert!(changes > 0);
+
+        // The other plan is not the cheapest with the current weights
+        let sensitivity = alternatives[0].sensitivity(&[best.clone()]).unwrap();
+        assert_eq!(sensitivity.ranges[1], None);
+        assert!(sensitivity.to_string().contains("map never"));
+    }
+}
@@ -0,0 +1,94 @@
+use crate::plan::{Plan, Undo};
+
+/// The edits made to a plan, so that any number of the latest ones can be reverted. Trying an
+/// alternative then only costs the states it changes, instead of a clone of the plan.
+///
+/// The log must only be used with the plan it was built with, and that plan must not be edited
+/// outside of the log while it has edits to revert.
+#[derive(Debug, Default)]
+pub struct UndoLog {
+    undos: Vec<Undo>,
+}
+
+impl UndoLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of edits in the log, to give to [`UndoLog::rollback()`]
+    pub fn len(&self) -> usize {
+        self.undos.len()
+    }
+
+    /// Record an edit just made to the plan, with [`Plan::apply_undoable()`] or [`Plan::reset()`]
+    pub fn push(&mut self, undo: Undo) {
+        self.undos.push(undo);
+    }
+
+    /// Revert the latest edits until only `len` remain
+    pub fn rollback(&mut self, plan: &mut Plan, len: usize) {
+        while self.undos.len() > len {
+            plan.undo(self.undos.pop().unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_order::NaturalOrder;
+    use crate::divider::Divider;
+    use crate::{Action, State};
+
+    #[test]
+    fn rollback() {
+        let divider = Divider::new();
+        let mut plan = Plan::new(State::new(6, 4));
+        let mut log = UndoLog::new();
+
+        let mut snapshots = vec![plan.to_string()];
+        while let Some((id, actions)) = plan.first_pending_actions(&divider, &NaturalOrder) {
+            log.push(plan.apply_undoable(id, *actions.last().unwrap()).unwrap());
+            snapshots.push(plan.to_string());
+        }
+        let complete = plan.clone();
+
+        // Re-plan the start another way, then go back to the complete plan
+        let start = plan.start();
+        log.push(plan.reset(start).unwrap());
+        assert_eq!(plan.plans().len(), 1);
+        assert_eq!(plan.to_string(), snapshots[0]);
+        log.push(plan.apply_undoable(start, Action::Throw).unwrap());
+        log.rollback(&mut plan, snapshots.len() - 1);
+        assert_eq!(plan, complete);
+        assert_eq!(plan.exact_cost(), complete.exact_cost());
+
+        for (len, snapshot) in snapshots.iter().enumerate().rev() {
+            log.rollback(&mut plan, len);
+            assert_eq!(plan.to_string(), *snapshot);
+        }
+        assert_eq!(log.len(), 0);
+    }
+
+    #[test]
+    fn reset_keeps_plan_valid() {
+        let divider = Divider::new();
+        let mut plan = Plan::new(State::new(6, 8));
+        while let Some((id, actions)) = plan.first_pending_actions(&divider, &NaturalOrder) {
+            plan.apply(id, actions[actions.len() / 2]).unwrap();
+        }
+
+        for id in plan.reachable_states() {
+            let mut reset = plan.clone();
+            match reset.reset(id) {
+                Ok(undo) => {
+                    assert_eq!(reset.validate(), Ok(()), "{}", reset);
+                    reset.undo(undo);
+                    assert_eq!(reset.to_string(), plan.to_string());
+                    assert_eq!(reset.plans().len(), plan.plans().len());
+                }
+                Err(_) => assert!(plan.state(id).solved()),
+            }
+        }
+    }
+}
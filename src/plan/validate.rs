@@ -0,0 +1,408 @@
+use crate::plan::{Plan, PlanBranch, StateId};
+use crate::State;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// Something that makes a plan inconsistent, with the states involved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanProblem {
+    /// A branch leads to a state that is not in the plan
+    Dangling {
+        state: State,
+        next: State,
+    },
+    MapDoesNotDivide {
+        state: State,
+        units: u32,
+    },
+    /// More units are mapped than the state has
+    MapTooManyUnits {
+        state: State,
+        units: u32,
+    },
+    /// A branch leads to another state than its action gives
+    WrongNext {
+        state: State,
+        expected: Option<State>,
+        actual: Option<State>,
+    },
+    SolvedNotTerminal {
+        state: State,
+    },
+    TerminalNotSolved {
+        state: State,
+    },
+    /// A state maps fewer units than required by the maps that lead to it as their remaining state
+    MapTooFewUnits {
+        state: State,
+        units: u32,
+        min_map_units: u32,
+    },
+    Unreachable {
+        state: State,
+    },
+    /// A state refers to the library, which has no plan for it
+    NotInLibrary {
+        state: State,
+    },
+}
+
+impl Plan {
+    /// Check that the plan is consistent, as plans built with [`Plan::apply()`] are, and report
+    /// all the problems otherwise
+    pub fn validate(&self) -> Result<(), Vec<PlanProblem>> {
+        let mut problems = Vec::new();
+
+        // The smallest number of units each state may map, given how it is reached
+        let mut min_map_units = HashMap::from([(self.start, 2)]);
+        for (_, branch) in &self.plans {
+            for (next, units) in branch.next_min_map_units() {
+                let min = min_map_units.entry(next).or_insert(units);
+                *min = (*min).min(units);
+            }
+        }
+
+        for (&id, branch) in &self.plans {
+            let state = self.state(id);
+            let min_map_units = min_map_units.get(&id).copied();
+            self.validate_branch(state, *branch, min_map_units, &mut problems);
+        }
+
+        let reachable = self.reachable_states().into_iter().collect::<HashSet<_>>();
+        for &id in self.plans.iter().map(|(id, _)| id) {
+            if !reachable.contains(&id) {
+                let state = self.state(id);
+                problems.push(PlanProblem::Unreachable { state });
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    fn validate_branch(
+        &self,
+        state: State,
+        branch: PlanBranch,
+        min_map_units: Option<u32>,
+        problems: &mut Vec<PlanProblem>,
+    ) {
+        for next in branch.next_states() {
+            if !self.plans.contains_key(&next) {
+                let next = self.state(next);
+                problems.push(PlanProblem::Dangling { state, next });
+            }
+        }
+
+        if state.solved() != matches!(branch, PlanBranch::Solved) {
+            problems.push(if state.solved() {
+                PlanProblem::TerminalNotSolved { state }
+            } else {
+                PlanProblem::SolvedNotTerminal { state }
+            });
+        }
+
+        let min_map_units = min_map_units.unwrap_or(2);
+        match branch {
+            PlanBranch::Pending {
+                min_map_units: units,
+            }
+            | PlanBranch::Map { units, .. }
+                if units < min_map_units =>
+            {
+                problems.push(PlanProblem::MapTooFewUnits {
+                    state,
+                    units,
+                    min_map_units,
+                });
+            }
+            _ => {}
+        }
+
+        let mut expect_next = |target, units, actual: Option<StateId>| {
+            let expected = (units > 0).then_some(State {
+                source: state.source,
+                target,
+                units,
+            });
+            let actual = actual.map(|actual| self.state(actual));
+            if actual != expected {
+                problems.push(PlanProblem::WrongNext {
+                    state,
+                    expected,
+                    actual,
+                });
+            }
+        };
+
+        match branch {
+            PlanBranch::Solved | PlanBranch::Pending { .. } => {}
+            PlanBranch::Library => {
+                let library = self.library.as_ref();
+                if library.and_then(|library| library.get(state)).is_none() {
+                    problems.push(PlanProblem::NotInLibrary { state });
+                }
+            }
+            PlanBranch::Throw { next } => {
+                expect_next(state.target, state.units * state.source, Some(next));
+            }
+            PlanBranch::Map { units, .. } if state.target % units != 0 => {
+                problems.push(PlanProblem::MapDoesNotDivide { state, units });
+            }
+            PlanBranch::Map { units, .. } if units > state.units => {
+                problems.push(PlanProblem::MapTooManyUnits { state, units });
+            }
+            PlanBranch::Map {
+                units,
+                sub_problem,
+                remaining,
+            } => {
+                expect_next(state.target / units, 1, Some(sub_problem));
+                expect_next(state.target, state.units - units, remaining);
+            }
+        }
+    }
+}
+
+impl Display for PlanProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            PlanProblem::Dangling { state, next } => {
+                write!(f, "{} leads to {}, which is not in the plan", state, next)
+            }
+            PlanProblem::MapDoesNotDivide { state, units } => {
+                write!(
+                    f,
+                    "{} maps {} units, which does not divide its target",
+                    state, units
+                )
+            }
+            PlanProblem::MapTooManyUnits { state, units } => {
+                write!(f, "{} maps {} units, more than it has", state, units)
+            }
+            PlanProblem::WrongNext {
+                state,
+                expected,
+                actual,
+            } => {
+                let describe = |next: Option<State>| {
+                    next.map_or_else(|| String::from("nothing"), |next| next.to_string())
+                };
+                write!(
+                    f,
+                    "{} leads to {} instead of {}",
+                    state,
+                    describe(actual),
+                    describe(expected)
+                )
+            }
+            PlanProblem::SolvedNotTerminal { state } => {
+                write!(f, "{} is solved but its target is not 1", state)
+            }
+            PlanProblem::TerminalNotSolved { state } => {
+                write!(f, "{} has a target of 1 but is not solved", state)
+            }
+            PlanProblem::MapTooFewUnits {
+                state,
+                units,
+                min_map_units,
+            } => write!(
+                f,
+                "{} maps {} units, but must map at least {}",
+                state, units, min_map_units
+            ),
+            PlanProblem::Unreachable { state } => {
+                write!(f, "{} cannot be reached from the start", state)
+            }
+            PlanProblem::NotInLibrary { state } => {
+                write!(
+                    f,
+                    "{} refers to the library, which has no plan for it",
+                    state
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_order::NaturalOrder;
+    use crate::divider::Divider;
+
+    #[test]
+    fn plans_built_by_apply_are_valid() {
+        fn visit(plan: &mut Plan, divider: &Divider, depth: u32) {
+            assert_eq!(plan.validate(), Ok(()), "{}", plan);
+
+            if depth > 0 {
+                for (state, action) in plan.possible_actions(divider, &NaturalOrder) {
+                    let undo = plan.apply_undoable(state, action).unwrap();
+                    visit(plan, divider, depth - 1);
+                    plan.undo(undo);
+                }
+            }
+        }
+
+        for (source, target) in [(6, 4), (2, 12), (5, 6)] {
+            visit(
+                &mut Plan::new(State::new(source, target)),
+                &Divider::new(),
+                4,
+            );
+        }
+    }
+
+    #[test]
+    fn problems() {
+        let plan = Plan::new(State::new(6, 4));
+        let id = |units, target| {
+            plan.interner.intern(State {
+                source: 6,
+                target,
+                units,
+            })
+        };
+        let state = |units, target| State {
+            source: 6,
+            target,
+            units,
+        };
+
+        let broken = Plan::from_branches(
+            plan.interner.clone(),
+            plan.start,
+            [
+                (id(1, 4), PlanBranch::Throw { next: id(5, 4) }),
+                (
+                    id(5, 4),
+                    PlanBranch::Map {
+                        units: 3,
+                        sub_problem: id(1, 1),
+                        remaining: Some(id(2, 4)),
+                    },
+                ),
+                (id(2, 4), PlanBranch::Solved),
+                (id(3, 4), PlanBranch::Pending { min_map_units: 2 }),
+                (
+                    id(6, 4),
+                    PlanBranch::Map {
+                        units: 2,
+                        sub_problem: id(1, 2),
+                        remaining: Some(id(3, 4)),
+                    },
+                ),
+                (
+                    id(4, 4),
+                    PlanBranch::Map {
+                        units: 2,
+                        sub_problem: id(1, 2),
+                        remaining: None,
+                    },
+                ),
+                (id(1, 2), PlanBranch::Pending { min_map_units: 2 }),
+                (
+                    id(8, 4),
+                    PlanBranch::Map {
+                        units: 2,
+                        sub_problem: id(1, 2),
+                        remaining: Some(id(6, 4)),
+                    },
+                ),
+                (
+                    id(12, 4),
+                    PlanBranch::Map {
+                        units: 4,
+                        sub_problem: id(1, 1),
+                        remaining: Some(id(8, 4)),
+                    },
+                ),
+                (
+                    id(9, 4),
+                    PlanBranch::Map {
+                        units: 2,
+                        sub_problem: id(1, 2),
+                        remaining: Some(id(7, 4)),
+                    },
+                ),
+                (id(7, 4), PlanBranch::Pending { min_map_units: 1 }),
+                (id(3, 1), PlanBranch::Pending { min_map_units: 2 }),
+                (
+                    id(1, 8),
+                    PlanBranch::Map {
+                        units: 2,
+                        sub_problem: id(1, 4),
+                        remaining: None,
+                    },
+                ),
+            ],
+        );
+
+        let problems = broken.validate().unwrap_err();
+        let expected = [
+            PlanProblem::WrongNext {
+                state: state(1, 4),
+                expected: Some(state(6, 4)),
+                actual: Some(state(5, 4)),
+            },
+            PlanProblem::Dangling {
+                state: state(5, 4),
+                next: state(1, 1),
+            },
+            PlanProblem::MapDoesNotDivide {
+                state: state(5, 4),
+                units: 3,
+            },
+            PlanProblem::SolvedNotTerminal { state: state(2, 4) },
+            PlanProblem::WrongNext {
+                state: state(6, 4),
+                expected: Some(state(4, 4)),
+                actual: Some(state(3, 4)),
+            },
+            PlanProblem::WrongNext {
+                state: state(4, 4),
+                expected: Some(state(2, 4)),
+                actual: None,
+            },
+            PlanProblem::Dangling {
+                state: state(12, 4),
+                next: state(1, 1),
+            },
+            PlanProblem::MapTooFewUnits {
+                state: state(8, 4),
+                units: 2,
+                min_map_units: 4,
+            },
+            PlanProblem::MapTooFewUnits {
+                state: state(7, 4),
+                units: 1,
+                min_map_units: 2,
+            },
+            PlanProblem::TerminalNotSolved { state: state(3, 1) },
+            PlanProblem::MapTooManyUnits {
+                state: state(1, 8),
+                units: 2,
+            },
+            PlanProblem::Unreachable { state: state(6, 4) },
+            PlanProblem::Unreachable { state: state(3, 4) },
+            PlanProblem::Unreachable { state: state(4, 4) },
+            PlanProblem::Unreachable { state: state(1, 2) },
+            PlanProblem::Unreachable { state: state(8, 4) },
+            PlanProblem::Unreachable {
+                state: state(12, 4),
+            },
+            PlanProblem::Unreachable { state: state(9, 4) },
+            PlanProblem::Unreachable { state: state(7, 4) },
+            PlanProblem::Unreachable { state: state(3, 1) },
+            PlanProblem::Unreachable { state: state(1, 8) },
+        ];
+        for problem in &expected {
+            assert!(problems.contains(problem), "missing {}", problem);
+        }
+        assert_eq!(problems.len(), expected.len(), "{:?}", problems);
+    }
+}
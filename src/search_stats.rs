@@ -0,0 +1,112 @@
+use itertools::Itertools;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// What a solver did to find its plan
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchStats {
+    /// Plans built by applying an action
+    pub generated: u64,
+    /// Generated plans whose actions were explored
+    pub expanded: u64,
+    /// Generated plans rejected because they were already visited
+    pub duplicates: u64,
+    /// Generated plans rejected because they cost more than the best complete plan
+    pub pruned: u64,
+    pub peak_queue: usize,
+    pub peak_visited: usize,
+    /// Time spent evaluating the cost of plans
+    pub cost_time: Duration,
+    /// Time spent applying actions and finding the actions of the next plans
+    pub expansion_time: Duration,
+    /// Time spent in the whole search
+    pub elapsed: Duration,
+    /// The best complete plan so far, each time it improved, starting with the initial one
+    pub improvements: Vec<Improvement>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Improvement {
+    pub elapsed: Duration,
+    pub cost: f64,
+}
+
+impl SearchStats {
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"generated\":{},\"expanded\":{},\"duplicates\":{},\"pruned\":{},\
+             \"peak_queue\":{},\"peak_visited\":{},\"cost_time\":{},\"expansion_time\":{},\
+             \"elapsed\":{},\"improvements\":[{}]}}",
+            self.generated,
+            self.expanded,
+            self.duplicates,
+            self.pruned,
+            self.peak_queue,
+            self.peak_visited,
+            self.cost_time.as_secs_f64(),
+            self.expansion_time.as_secs_f64(),
+            self.elapsed.as_secs_f64(),
+            self.improvements.iter().format_with(",", |improvement, f| {
+                f(&format_args!(
+                    "{{\"elapsed\":{},\"cost\":{}}}",
+                    improvement.elapsed.as_secs_f64(),
+                    improvement.cost
+                ))
+            })
+        )
+    }
+
+    pub(crate) fn improved(&mut self, elapsed: Duration, cost: f64) {
+        self.improvements.push(Improvement { elapsed, cost });
+    }
+}
+
+/// A table with one statistic per line
+impl Display for SearchStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:<16}{:>16}", "generated", self.generated)?;
+        writeln!(f, "{:<16}{:>16}", "expanded", self.expanded)?;
+        writeln!(f, "{:<16}{:>16}", "duplicates", self.duplicates)?;
+        writeln!(f, "{:<16}{:>16}", "pruned", self.pruned)?;
+        writeln!(f, "{:<16}{:>16}", "peak queue", self.peak_queue)?;
+        writeln!(f, "{:<16}{:>16}", "peak visited", self.peak_visited)?;
+        writeln!(f, "{:<16}{:>16.3?}", "cost time", self.cost_time)?;
+        writeln!(f, "{:<16}{:>16.3?}", "expansion time", self.expansion_time)?;
+        writeln!(f, "{:<16}{:>16.3?}", "elapsed", self.elapsed)?;
+        writeln!(f, "improvements")?;
+        for improvement in &self.improvements {
+            writeln!(f, "{:>16.3?}  {}", improvement.elapsed, improvement.cost)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_json() {
+        let mut stats = SearchStats {
+            generated: 10,
+            expanded: 5,
+            duplicates: 3,
+            pruned: 2,
+            peak_queue: 7,
+            peak_visited: 8,
+            cost_time: Duration::from_millis(250),
+            expansion_time: Duration::from_millis(500),
+            elapsed: Duration::from_secs(1),
+            improvements: Vec::new(),
+        };
+        stats.improved(Duration::ZERO, 2.0);
+        stats.improved(Duration::from_millis(750), 1.5);
+
+        assert_eq!(
+            stats.to_json(),
+            "{\"generated\":10,\"expanded\":5,\"duplicates\":3,\"pruned\":2,\"peak_queue\":7,\
+             \"peak_visited\":8,\"cost_time\":0.25,\"expansion_time\":0.5,\"elapsed\":1,\
+             \"improvements\":[{\"elapsed\":0,\"cost\":2},{\"elapsed\":0.75,\"cost\":1.5}]}"
+        );
+    }
+}
@@ -0,0 +1,16 @@
+use crate::action_order::NaturalOrder;
+use crate::best_solver::best_solver;
+use crate::plan::{CostModel, Objective, Plan};
+use crate::State;
+
+/// The plan with the fewest expected throws, found without a heuristic
+pub fn best(start: State) -> Plan {
+    let objective = Objective::ExpectedThrows;
+    let cost_model = CostModel::default();
+    best_solver(start, |_| 0.0, &NaturalOrder, objective, cost_model, None).0
+}
+
+/// Costs computed in different ways differ by rounding errors
+pub fn assert_close(a: f64, b: f64) {
+    assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+}